@@ -21,11 +21,10 @@ impl From<Accidental> for i32 {
     }
 }
 
-impl Add for Accidental {
-    type Output = Accidental;
-
-    fn add(self, rhs: Accidental) -> Self::Output {
-        match i32::from(self) + i32::from(rhs) {
+impl From<i32> for Accidental {
+    ///the accidental raising by `n` half steps, or lowering if negative
+    fn from(n: i32) -> Self {
+        match n {
             -1 => Self::Flat,
             0 => Self::Natural,
             1 => Self::Sharp,
@@ -40,6 +39,14 @@ impl Add for Accidental {
     }
 }
 
+impl Add for Accidental {
+    type Output = Accidental;
+
+    fn add(self, rhs: Accidental) -> Self::Output {
+        Self::from(i32::from(self) + i32::from(rhs))
+    }
+}
+
 impl AddAssign for Accidental {
     fn add_assign(&mut self, rhs: Self) {
         *self = *self + rhs;
@@ -0,0 +1,19 @@
+///How a note is played, written after its duration
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Articulation {
+    ///sounds for half its length
+    Staccato,
+    ///starts with a stronger attack
+    Accent,
+    ///sounds for its full length
+    Tenuto,
+}
+
+impl Articulation {
+    ///the share of its length a note sounds for if not otherwise articulated
+    pub const NON_LEGATO: f32 = 0.9;
+    ///the attack of an accented note relative to a normal one
+    pub const ACCENT: f32 = 1.5;
+    ///the attack of a slurred note relative to a normal one
+    pub const SLURRED: f32 = 0.5;
+}
@@ -1,118 +1,65 @@
-use {
-    fraction::{GenericFraction, ToPrimitive},
-    std::{iter::Sum, time},
-};
+use {fraction::GenericFraction, std::iter::Sum};
 
 pub type Fraction = GenericFraction<u32>;
 
+///The length of a note in whole notes, converted to time by a `TempoMap`
 #[derive(Debug, Copy, Clone, PartialEq, Eq)]
 pub struct Duration {
-    whole_note: time::Duration,
     pub fraction: Fraction,
 }
 
 impl Duration {
-    pub fn as_secs(&self) -> f32 {
-        time::Duration::from(*self).as_secs_f32()
+    ///a 1/`nth` note with `dots` dots
+    pub fn new(nth: u32, dots: u32) -> Self {
+        Self {
+            //each dot adds half of the last part
+            fraction: (0..=dots)
+                .map(|dot| Fraction::new(1u32, nth << dot))
+                .sum::<Fraction>(),
+        }
+    }
+
+    pub fn scale(self, by: Fraction) -> Self {
+        Self {
+            fraction: self.fraction * by,
+        }
     }
 }
 
-impl From<Duration> for time::Duration {
-    fn from(d: Duration) -> Self {
-        d.whole_note.mul_f32(d.fraction.to_f32().unwrap())
+impl From<Fraction> for Duration {
+    fn from(fraction: Fraction) -> Self {
+        Self { fraction }
     }
 }
 
 impl<'a> Sum<&'a Self> for Duration {
     fn sum<I: Iterator<Item = &'a Self>>(iter: I) -> Self {
-        iter.map(|v| *v).sum::<Self>()
+        iter.copied().sum::<Self>()
     }
 }
 
 impl Sum for Duration {
     fn sum<I: Iterator<Item = Self>>(iter: I) -> Self {
-        let (whole_notes, fractions) = iter
-            .map(|d| (d.whole_note, d.fraction))
-            .unzip::<time::Duration, Fraction, Vec<_>, Vec<_>>();
-        let whole_note = whole_notes
-            .into_iter()
-            .reduce(|a, b| {
-                if a == b {
-                    a
-                } else {
-                    panic!("Attempted to add note durations with different time durations")
-                }
-            })
-            .unwrap();
         Self {
-            whole_note,
-            fraction: fractions.into_iter().sum::<Fraction>(),
+            fraction: iter.map(|d| d.fraction).sum::<Fraction>(),
         }
     }
 }
 
-pub struct DurationBuilder(time::Duration);
-
-impl DurationBuilder {
-    pub fn new(dur: time::Duration) -> Self {
-        Self(dur)
-    }
-
-    pub fn from_bpm(bpm: u32) -> Self {
-        DurationBuilder(time::Duration::new(4 * 60, 0).div_f32(bpm as f32))
-    }
-
-    pub fn build(&self, nth: u32, dots: u32) -> Duration {
-        Duration {
-            whole_note: self.0,
-            fraction: (0..=dots)
-                .scan(Fraction::new(1u32, nth), |frac, _nth_dot| {
-                    let old = *frac;
-                    *frac = (1, frac.denom().unwrap() * 2).into();
-                    Some(old)
-                })
-                .sum::<Fraction>(),
-        }
-    }
-}
-
-impl From<Duration> for DurationBuilder {
-    fn from(d: Duration) -> Self {
-        Self::new(d.whole_note)
-    }
-}
-
 #[test]
-fn test_bpm_builder() {
-    let mut dur = DurationBuilder::from_bpm(120);
-    assert_eq!(
-        time::Duration::from(dur.build(4, 0)),
-        time::Duration::from_secs_f32(0.5)
-    );
-    assert_eq!(
-        time::Duration::from(dur.build(4, 1)),
-        time::Duration::from_secs_f32(0.75)
-    );
-    assert_eq!(
-        time::Duration::from(dur.build(1, 0)),
-        time::Duration::from_secs_f32(2.)
-    );
-    assert_eq!(
-        time::Duration::from(
-            [dur.build(4, 0), dur.build(4, 1), dur.build(1, 0)]
-                .iter()
-                .sum::<Duration>()
-        ),
-        time::Duration::from_secs_f32(3.25),
-    );
-
-    dur = DurationBuilder::from_bpm(80);
-    assert_eq!(
-        time::Duration::from(dur.build(4, 0)),
-        time::Duration::from_secs_f32(0.75)
-    );
+fn test_duration() {
+    assert_eq!(Duration::new(4, 0).fraction, Fraction::new(1u32, 4u32));
+    assert_eq!(Duration::new(4, 1).fraction, Fraction::new(3u32, 8u32));
+    assert_eq!(Duration::new(2, 2).fraction, Fraction::new(7u32, 8u32));
     assert_eq!(
-        time::Duration::from(dur.build(1, 0)),
-        time::Duration::from_secs_f32(3.)
+        [
+            Duration::new(4, 0),
+            Duration::new(4, 1),
+            Duration::new(1, 0)
+        ]
+        .iter()
+        .sum::<Duration>()
+        .fraction,
+        Fraction::new(13u32, 8u32),
     );
 }
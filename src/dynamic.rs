@@ -0,0 +1,33 @@
+///How loud notes are played, from pianissimo to fortissimo
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Dynamic {
+    PP,
+    P,
+    MP,
+    ///notes are played mezzo forte until told otherwise
+    #[default]
+    MF,
+    F,
+    FF,
+}
+
+impl Dynamic {
+    ///the factor the envelope of a note is scaled by
+    pub fn velocity(&self) -> f32 {
+        match self {
+            Self::PP => 0.25,
+            Self::P => 0.4,
+            Self::MP => 0.55,
+            Self::MF => 0.7,
+            Self::F => 0.85,
+            Self::FF => 1.,
+        }
+    }
+}
+
+///A change of dynamic spread over the notes up to the next dynamic
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Hairpin {
+    Crescendo,
+    Diminuendo,
+}
@@ -0,0 +1,214 @@
+use {
+    crate::{
+        accidental::Accidental,
+        key::Key,
+        note::{Note, Pitch},
+    },
+    std::{
+        convert::TryFrom,
+        ops::{Add, Neg},
+    },
+};
+
+type Tone = (Pitch, i32, Accidental);
+
+///the most octaves notes can be transposed by
+pub const MAX_OCTAVES: i32 = 10;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Interval {
+    ///a number of half steps, spelled to suit the key
+    Semitones(i32),
+    ///a number of letter steps spanning a number of half steps
+    Diatonic { steps: i32, semitones: i32 },
+}
+
+impl TryFrom<(char, u32)> for Interval {
+    type Error = &'static str;
+
+    ///the interval with a quality, `P`, `M`, `m`, `A` or `d`, and a number, e.g. `M2` or `P5`
+    fn try_from((quality, number): (char, u32)) -> Result<Self, Self::Error> {
+        const MAJOR: [i32; 7] = [0, 2, 4, 5, 7, 9, 11];
+        let steps = match i32::try_from(number) {
+            Ok(number @ 1..) if number <= 7 * MAX_OCTAVES + 1 => number - 1,
+            _ => return Err("Invalid Interval"),
+        };
+        let degree = steps.rem_euclid(7);
+        let major = MAJOR[degree as usize] + 12 * steps.div_euclid(7);
+        let perfect = matches!(degree, 0 | 3 | 4);
+        let semitones = match (quality, perfect) {
+            ('P', true) | ('M', false) => major,
+            ('m', false) | ('d', true) => major - 1,
+            ('d', false) => major - 2,
+            ('A', _) => major + 1,
+            _ => return Err("Invalid Interval"),
+        };
+        Ok(Self::Diatonic { steps, semitones })
+    }
+}
+
+impl TryFrom<i32> for Interval {
+    type Error = &'static str;
+
+    ///the interval of `semitones` half steps
+    fn try_from(semitones: i32) -> Result<Self, Self::Error> {
+        if semitones.abs() <= 12 * MAX_OCTAVES {
+            Ok(Self::Semitones(semitones))
+        } else {
+            Err("Transposition is too wide")
+        }
+    }
+}
+
+impl Neg for Interval {
+    type Output = Self;
+
+    fn neg(self) -> Self::Output {
+        match self {
+            Self::Semitones(semitones) => Self::Semitones(-semitones),
+            Self::Diatonic { steps, semitones } => Self::Diatonic {
+                steps: -steps,
+                semitones: -semitones,
+            },
+        }
+    }
+}
+
+///Two intervals one after the other, spelled for the key only if either is in half steps alone
+impl Add for Interval {
+    type Output = Self;
+
+    fn add(self, other: Self) -> Self::Output {
+        match (self, other) {
+            (
+                Self::Diatonic { steps, semitones },
+                Self::Diatonic {
+                    steps: s,
+                    semitones: n,
+                },
+            ) => Self::Diatonic {
+                steps: steps + s,
+                semitones: semitones + n,
+            },
+            _ => Self::Semitones(self.semitones() + other.semitones()),
+        }
+    }
+}
+
+impl Interval {
+    ///the half steps the interval spans
+    pub fn semitones(self) -> i32 {
+        match self {
+            Self::Semitones(semitones) | Self::Diatonic { semitones, .. } => semitones,
+        }
+    }
+
+    ///the letter steps and half steps of the interval from `key`.
+    ///Half steps alone, at most `MAX_OCTAVES` octaves of them,
+    ///are spelled to give the key they lead to the fewest accidentals
+    pub fn diatonic(self, key: &Key) -> (i32, i32) {
+        match self {
+            Self::Diatonic { steps, semitones } => (steps, semitones),
+            Self::Semitones(semitones) => {
+                let steps = (semitones * 7).div_euclid(12);
+                [steps, steps + 1]
+                    .iter()
+                    .map(|&steps| (steps, semitones))
+                    .min_by_key(|&(steps, semitones)| {
+                        key.transpose(Self::Diatonic { steps, semitones })
+                            .accidentals()
+                    })
+                    .unwrap()
+            }
+        }
+    }
+}
+
+///the tone `steps` letters and `semitones` half steps higher
+pub fn shift_tone((p, oct, acc): Tone, (steps, semitones): (i32, i32)) -> Tone {
+    use Pitch::C;
+    let (to, octaves) = p.step(steps);
+    let n = oct * 12 + (C - p) + i32::from(acc) + semitones;
+    let natural = (oct + octaves) * 12 + (C - to);
+    (to, oct + octaves, Accidental::from(n - natural))
+}
+
+///the notes `interval` higher than in `key`, spelled for the key they are transposed to
+pub fn transpose(notes: &[Note], interval: Interval, key: &Key) -> Vec<Note> {
+    let interval = interval.diatonic(key);
+    notes
+        .iter()
+        .cloned()
+        .map(|note| note.map_tones(|tone| shift_tone(tone, interval)))
+        .collect()
+}
+
+#[cfg(test)]
+use {crate::duration::Duration, Accidental::*, Pitch::*};
+
+#[test]
+fn test_transpose() {
+    let quarter = Duration::new(4, 0);
+    let notes = [
+        Note::Note(C, 4, Natural, quarter),
+        Note::Note(F, 4, Sharp, quarter),
+        Note::Chord(vec![(B, 4, Flat), (E, 5, Natural)], quarter),
+    ];
+    let tones = |notes: Vec<Note>| notes.iter().flat_map(Note::tones).collect::<Vec<_>>();
+    let key = Key::default();
+
+    let major_second = Interval::try_from(('M', 2)).unwrap();
+    assert_eq!(
+        tones(transpose(&notes, major_second, &key)),
+        vec![
+            (D, 4, Natural),
+            (G, 4, Sharp),
+            (C, 5, Natural),
+            (F, 5, Sharp)
+        ]
+    );
+    assert_eq!(
+        tones(transpose(&notes, Interval::Semitones(1), &key)),
+        vec![(D, 4, Flat), (G, 4, Natural), (C, 5, Flat), (F, 5, Natural)]
+    );
+    assert_eq!(
+        tones(transpose(
+            &notes,
+            -Interval::try_from(('m', 3)).unwrap(),
+            &key
+        )),
+        vec![
+            (A, 3, Natural),
+            (D, 4, Sharp),
+            (G, 4, Natural),
+            (C, 5, Sharp)
+        ]
+    );
+    assert_eq!(Interval::Semitones(2).diatonic(&key), (1, 2));
+    assert_eq!(
+        Interval::try_from(('d', 5)),
+        Ok(Interval::Diatonic {
+            steps: 4,
+            semitones: 6
+        })
+    );
+    assert_eq!(
+        Interval::try_from(('A', 4)),
+        Ok(Interval::Diatonic {
+            steps: 3,
+            semitones: 6
+        })
+    );
+    assert!(Interval::try_from(('P', 3)).is_err());
+    assert!(Interval::try_from(('M', 0)).is_err());
+    assert!(Interval::try_from(('M', u32::MAX)).is_err());
+    assert!(Interval::try_from(i32::MAX).is_err());
+
+    for (p, acc) in [(A, Sharp), (B, Flat), (G, NSharp(2))] {
+        let note = Note::Note(p, 4, acc, quarter);
+        let freq = note.frequencies()[0];
+        let up = transpose(&[note], Interval::Semitones(7), &key);
+        let ratio = up[0].frequencies()[0] / freq;
+        assert!((ratio - 2f32.powf(7. / 12.)).abs() < 1e-3);
+    }
+}
@@ -1,36 +1,159 @@
 use {
-    crate::{accidental::Accidental, note::Pitch},
+    crate::{
+        accidental::Accidental,
+        interval::{shift_tone, Interval},
+        note::Pitch,
+    },
     std::{collections::HashMap, convert::TryFrom},
 };
 
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
 pub enum Mode {
     Major,
     Minor,
+    Dorian,
+    Phrygian,
+    Lydian,
+    Mixolydian,
+    Locrian,
+    ///minor with a raised 7th
+    HarmonicMinor,
+    ///minor with a raised 6th and 7th
+    MelodicMinor,
 }
-impl TryFrom<char> for Mode {
+
+impl Mode {
+    ///the fifths between the tonic of the mode and that of its parent major key
+    fn fifths(&self) -> i32 {
+        match self {
+            Self::Lydian => 1,
+            Self::Major => 0,
+            Self::Mixolydian => -1,
+            Self::Dorian => -2,
+            Self::Minor | Self::HarmonicMinor | Self::MelodicMinor => -3,
+            Self::Phrygian => -4,
+            Self::Locrian => -5,
+        }
+    }
+
+    ///the degrees of the scale raised from the parent major key, counted from 0
+    fn raised(&self) -> &'static [i32] {
+        match self {
+            Self::HarmonicMinor => &[6],
+            Self::MelodicMinor => &[5, 6],
+            _ => &[],
+        }
+    }
+}
+
+impl TryFrom<&str> for Mode {
     type Error = &'static str;
 
-    fn try_from(c: char) -> Result<Self, Self::Error> {
-        match c {
-            'M' => Ok(Self::Major),
-            'm' => Ok(Self::Minor),
+    fn try_from(s: &str) -> Result<Self, Self::Error> {
+        match s {
+            "" | "M" | "Ion" => Ok(Self::Major),
+            "m" | "Aeo" => Ok(Self::Minor),
+            "Dor" => Ok(Self::Dorian),
+            "Phr" => Ok(Self::Phrygian),
+            "Lyd" => Ok(Self::Lydian),
+            "Mix" => Ok(Self::Mixolydian),
+            "Loc" => Ok(Self::Locrian),
+            "Harm" => Ok(Self::HarmonicMinor),
+            "Mel" => Ok(Self::MelodicMinor),
             _ => Err("Invalid Mode"),
         }
     }
 }
 
+#[derive(Clone)]
 pub struct Key {
     pub oct: i32,
     map: HashMap<Pitch, Accidental>,
+    ///accidentals written earlier in the current measure
+    carried: HashMap<(Pitch, i32), Accidental>,
 }
 
 impl Key {
+    ///A key with the given accidentals, each pitch given at most once
+    pub fn with_accidentals(
+        oct: i32,
+        accidentals: Vec<(Pitch, Accidental)>,
+    ) -> Result<Self, &'static str> {
+        let count = accidentals.len();
+        let map = accidentals.into_iter().collect::<HashMap<_, _>>();
+        if map.len() < count {
+            Err("Pitch given more than once in key")
+        } else {
+            Ok(Self {
+                oct,
+                map,
+                carried: HashMap::new(),
+            })
+        }
+    }
+
     pub fn get(&self, pitch: &Pitch) -> &Accidental {
         match self.map.get(pitch) {
             Some(acc) => acc,
             None => &Accidental::Natural,
         }
     }
+
+    ///the accidental of `pitch` in octave `oct`,
+    ///carried from earlier in the measure or else from the key signature
+    pub fn accidental(&self, pitch: &Pitch, oct: i32) -> &Accidental {
+        match self.carried.get(&(*pitch, oct)) {
+            Some(acc) => acc,
+            None => self.get(pitch),
+        }
+    }
+
+    ///makes the accidental of a written tone apply to the later notes
+    ///of the same pitch and octave, until the end of the measure
+    pub fn carry(&mut self, (pitch, oct, acc): (Pitch, i32, Accidental)) {
+        self.carried.insert((pitch, oct), acc);
+    }
+
+    pub fn end_measure(&mut self) {
+        self.carried.clear();
+    }
+
+    ///the key `interval` higher, with the accidentals carried through the measure
+    pub fn transpose(&self, interval: Interval) -> Self {
+        use Pitch::*;
+        let interval = interval.diatonic(self);
+        let map = [C, D, E, F, G, A, B]
+            .iter()
+            .map(|&p| shift_tone((p, 0, *self.get(&p)), interval))
+            .filter(|(_, _, acc)| *acc != Accidental::Natural)
+            .map(|(p, _, acc)| (p, acc))
+            .collect();
+        let carried = self
+            .carried
+            .iter()
+            .map(|(&(p, oct), &acc)| {
+                let (p, oct, acc) = shift_tone((p, oct, acc), interval);
+                ((p, oct), acc)
+            })
+            .collect();
+        Self {
+            oct: self.oct,
+            map,
+            carried,
+        }
+    }
+
+    ///the number of half steps the key signature changes its pitches by
+    pub fn accidentals(&self) -> i32 {
+        self.map.values().map(|&acc| i32::from(acc).abs()).sum()
+    }
+
+    ///the tone `steps` letters from `pitch` in octave `oct`, with the accidental in effect
+    pub fn step(&self, pitch: Pitch, oct: i32, steps: i32) -> (Pitch, i32, Accidental) {
+        let (pitch, octaves) = pitch.step(steps);
+        let oct = oct + octaves;
+        (pitch, oct, *self.accidental(&pitch, oct))
+    }
 }
 
 impl Default for Key {
@@ -38,58 +161,109 @@ impl Default for Key {
         Self {
             oct: 4,
             map: HashMap::new(),
+            carried: HashMap::new(),
         }
     }
 }
 
-macro_rules! key {
-    ($p:pat, $m:pat) => {
-        ($p, Accidental::Natural, $m)
-    };
-
-    ($p:pat, #, $m:pat) => {
-        ($p, Accidental::Sharp, $m)
-    };
-
-    ($p:pat, b, $m:pat) => {
-        ($p, Accidental::Flat, $m)
-    };
-
-    (# $($p:expr),+) => {
-        vec![$(($p, Accidental::Sharp)),*].into_iter().collect()
-    };
-
-    (b $($p:expr),+) => {
-        vec![$(($p, Accidental::Flat)),*].into_iter().collect()
+///the number of sharps, or flats if negative, in the major key of a tonic
+fn fifths(pitch: Pitch, acc: Accidental) -> i32 {
+    use Pitch::*;
+    let natural = match pitch {
+        F => -1,
+        C => 0,
+        G => 1,
+        D => 2,
+        A => 3,
+        E => 4,
+        B => 5,
     };
+    natural + 7 * i32::from(acc)
 }
 
 impl TryFrom<(Pitch, i32, Accidental, Mode)> for Key {
     type Error = &'static str;
 
-    fn try_from(key: (Pitch, i32, Accidental, Mode)) -> Result<Self, Self::Error> {
-        use {Mode::*, Pitch::*};
-        let (key, oct) = ((key.0, key.2, key.3), key.1);
+    ///Derives the key signature from the major key the mode belongs to,
+    ///which must have at most 7 sharps or flats
+    fn try_from(
+        (pitch, oct, acc, mode): (Pitch, i32, Accidental, Mode),
+    ) -> Result<Self, Self::Error> {
+        use Pitch::*;
+        let fifths = fifths(pitch, acc) + mode.fifths();
+        if fifths.abs() > 7 {
+            return Err("Invalid Key");
+        }
+
+        let mut map = if fifths > 0 {
+            [F, C, G, D, A, E, B][..fifths as usize]
+                .iter()
+                .map(|&p| (p, Accidental::Sharp))
+                .collect::<HashMap<_, _>>()
+        } else {
+            [B, E, A, D, G, C, F][..-fifths as usize]
+                .iter()
+                .map(|&p| (p, Accidental::Flat))
+                .collect()
+        };
+        for &degree in mode.raised() {
+            let (p, _) = pitch.step(degree);
+            let raised = *map.get(&p).unwrap_or(&Accidental::Natural) + Accidental::Sharp;
+            map.insert(p, raised);
+        }
+
         Ok(Self {
             oct,
-            map: match key {
-                key!(C, Major) | key!(A, Minor) => HashMap::new(),
-                key!(G, Major) | key!(E, Minor) => key![# F],
-                key!(D, Major) | key!(B, Minor) => key![# F, C],
-                key!(A, Major) | key!(F, #, Minor) => key![# F, C, G],
-                key!(E, Major) | key!(C, #, Minor) => key![# F, C, G, D],
-                key!(B, Major) | key!(G, #, Minor) => key![# F, C, G, D, A],
-                key!(F, #, Major) | key!(D, #, Minor) => key![# F, C, G, D, A, E],
-                key!(C, #, Major) | key!(A, #, Minor) => key![# F, C, G, D, A, E, B],
-                key!(F, Major) | key!(D, Minor) => key![b B],
-                key!(B, b, Major) | key!(G, Minor) => key![b B, E],
-                key!(E, b, Major) | key!(C, Minor) => key![b B, E, A],
-                key!(A, b, Major) | key!(F, Minor) => key![b B, E, A, D],
-                key!(D, b, Major) | key!(B, b, Minor) => key![b B, E, A, D, G],
-                key!(G, b, Major) | key!(E, b, Minor) => key![b B, E, A, D, G, C],
-                key!(C, b, Major) | key!(A, b, Minor) => key![b B, E, A, D, G, C, F],
-                _ => Err("Invalid Key")?,
-            },
+            map,
+            carried: HashMap::new(),
         })
     }
 }
+
+#[cfg(test)]
+use Pitch::*;
+
+#[test]
+fn test_modes() {
+    let key = |p, acc, mode| {
+        let key = Key::try_from((p, 4, acc, mode)).unwrap();
+        let mut map = [C, D, E, F, G, A, B]
+            .iter()
+            .map(|p| (*p, i32::from(*key.get(p))))
+            .filter(|(_, acc)| *acc != 0)
+            .collect::<Vec<_>>();
+        map.sort_by_key(|(p, _)| format!("{:?}", p));
+        map
+    };
+    use Accidental::*;
+
+    assert_eq!(key(D, Natural, Mode::Major), vec![(C, 1), (F, 1)]);
+    assert_eq!(
+        key(C, Natural, Mode::Minor),
+        vec![(A, -1), (B, -1), (E, -1)]
+    );
+    assert_eq!(key(C, Sharp, Mode::Major).len(), 7);
+    assert_eq!(key(C, Flat, Mode::Major).len(), 7);
+    assert!(key(D, Natural, Mode::Dorian).is_empty());
+    assert!(key(E, Natural, Mode::Phrygian).is_empty());
+    assert!(key(F, Natural, Mode::Lydian).is_empty());
+    assert!(key(B, Natural, Mode::Locrian).is_empty());
+    assert_eq!(key(G, Natural, Mode::Dorian), vec![(B, -1)]);
+    assert_eq!(key(A, Natural, Mode::HarmonicMinor), vec![(G, 1)]);
+    assert_eq!(key(C, Natural, Mode::MelodicMinor), vec![(E, -1)]);
+    assert_eq!(key(E, Natural, Mode::HarmonicMinor), vec![(D, 1), (F, 1)]);
+    assert!(Key::try_from((G, 4, Sharp, Mode::Major)).is_err());
+    assert!(Key::try_from((G, 4, Sharp, Mode::Locrian)).is_ok());
+}
+
+#[test]
+fn test_custom_key() {
+    let key =
+        Key::with_accidentals(4, vec![(F, Accidental::Sharp), (B, Accidental::Flat)]).unwrap();
+    assert_eq!(*key.get(&F), Accidental::Sharp);
+    assert_eq!(*key.get(&B), Accidental::Flat);
+    assert_eq!(*key.get(&C), Accidental::Natural);
+    assert!(
+        Key::with_accidentals(4, vec![(F, Accidental::Sharp), (F, Accidental::Natural)]).is_err()
+    );
+}
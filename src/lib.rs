@@ -0,0 +1,14 @@
+pub mod accidental;
+pub mod articulation;
+pub mod duration;
+pub mod dynamic;
+mod envelope;
+pub mod interval;
+pub mod key;
+pub mod lyrics;
+pub mod note;
+pub mod ornament;
+pub mod parse;
+pub mod score;
+pub mod signature;
+pub mod tempo;
@@ -0,0 +1,65 @@
+///A syllable of a lyric line, sung to one note
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Syllable {
+    pub text: String,
+    ///whether the word continues with the next syllable
+    pub hyphen: bool,
+    ///whether held over the notes following its own
+    pub melisma: bool,
+    ///whether the first syllable of its line
+    pub line_start: bool,
+    ///the verse, from 0, sung the time the note is played after as many others
+    pub verse: u32,
+}
+
+///Splits a lyric line into the syllable sung to each note.
+///Words are split into syllables by `-`, `_` holds the previous syllable over another note
+///and `*` skips a note, both giving `None`
+pub fn syllables(line: &str) -> Vec<Option<Syllable>> {
+    let mut syllables: Vec<Option<Syllable>> = Vec::new();
+    for word in line.split_whitespace() {
+        match word {
+            "_" => {
+                if let Some(Some(last)) = syllables.iter_mut().rev().find(|s| s.is_some()) {
+                    last.melisma = true;
+                }
+                syllables.push(None);
+            }
+            "*" => syllables.push(None),
+            _ => {
+                let parts = word.split('-').collect::<Vec<_>>();
+                for (i, part) in parts.iter().enumerate().filter(|(_, p)| !p.is_empty()) {
+                    syllables.push(Some(Syllable {
+                        text: part.to_string(),
+                        hyphen: i + 1 < parts.len(),
+                        melisma: false,
+                        line_start: false,
+                        verse: 0,
+                    }));
+                }
+            }
+        }
+    }
+    if let Some(Some(first)) = syllables.iter_mut().find(|s| s.is_some()) {
+        first.line_start = true;
+    }
+    syllables
+}
+
+#[test]
+fn test_syllables() {
+    let syllables = syllables("Zan-ko-ku na * ten- shi _ _ no");
+    let texts = syllables
+        .iter()
+        .map(|s| s.as_ref().map_or("", |s| s.text.as_str()))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        texts,
+        vec!["Zan", "ko", "ku", "na", "", "ten", "shi", "", "", "no"]
+    );
+    let zan = syllables[0].as_ref().unwrap();
+    assert!(zan.hyphen && zan.line_start && !zan.melisma);
+    assert!(syllables[5].as_ref().unwrap().hyphen);
+    assert!(syllables[6].as_ref().unwrap().melisma);
+    assert!(!syllables[9].as_ref().unwrap().hyphen);
+}
@@ -1,23 +1,87 @@
 use {
-    crate::note::Sound,
-    parse::parse,
-    rodio::{OutputStream, Sink},
+    music::{
+        note::{Sound, SAMPLE_RATE},
+        parse::{parse, parse_lenient},
+        score::Event,
+    },
+    rodio::{dynamic_mixer, source, OutputStream, Sink, Source},
+    std::{
+        env, fs,
+        io::{self, Write},
+        process, thread,
+    },
 };
 
-mod accidental;
-mod duration;
-mod envelope;
-mod key;
-mod note;
-mod parse;
+///Reports every error in the given score files, exiting with 1 if any were found
+fn lint(paths: &[String]) {
+    let mut failed = false;
+    for path in paths {
+        let txt = fs::read_to_string(path).unwrap_or_else(|e| {
+            eprintln!("{}: {}", path, e);
+            process::exit(2);
+        });
+        for e in parse_lenient(&txt).1 {
+            failed = true;
+            eprintln!("{}", e.render(&txt, path));
+        }
+    }
+    process::exit(failed as i32);
+}
+
+///Prints each syllable as it is sung, starting a new line with each lyric line
+fn karaoke(events: &[Event]) {
+    for event in events {
+        if let Some(syllable) = &event.lyric {
+            if syllable.line_start {
+                println!();
+            }
+            let end = if syllable.hyphen { "-" } else { " " };
+            print!("{}{}", syllable.text, end);
+            io::stdout().flush().unwrap();
+        }
+        thread::sleep(event.length);
+    }
+    println!();
+}
 
 fn main() {
+    let args = env::args().skip(1).collect::<Vec<_>>();
+    if let Some("lint") = args.first().map(String::as_str) {
+        lint(&args[1..]);
+    }
+
+    let txt = include_str!("../a_cruel_angels_thesis.txt");
+    let score = parse(txt).unwrap_or_else(|e| {
+        eprint!("{}", e.render(txt, "a_cruel_angels_thesis.txt"));
+        process::exit(1);
+    });
+
+    if let Some(title) = &score.metadata.title {
+        println!("{}", title);
+    }
+
     let (_stream, stream_handle) = OutputStream::try_default().unwrap();
     let sink = Sink::try_new(&stream_handle).unwrap();
+    let (controller, mixer) = dynamic_mixer::mixer(1, SAMPLE_RATE);
 
-    for n in parse(include_str!("../a_cruel_angels_thesis.txt")).into_iter() {
-        sink.append::<Sound>(n.into());
+    let volume = 1.0 / score.voices.len().max(1) as f32;
+    let tempo = score.tempo_map();
+    let voices = score
+        .voices
+        .iter()
+        .map(|voice| voice.events(&tempo))
+        .collect::<Vec<_>>();
+    for events in &voices {
+        let sounds = events.clone().into_iter().map(Sound::from);
+        controller.add(source::from_iter(sounds).amplify(volume));
     }
 
+    sink.append(mixer);
+    if let Some(events) = voices
+        .iter()
+        .find(|events| events.iter().any(|e| e.lyric.is_some()))
+    {
+        karaoke(events);
+    }
     sink.sleep_until_end();
 }
@@ -1,5 +1,5 @@
 use {
-    crate::{accidental::Accidental, duration::Duration, envelope::Piano},
+    crate::{accidental::Accidental, duration::Duration, envelope::Piano, score::Event},
     rodio::source::Source,
     std::{convert::TryFrom, f32::consts::PI, ops::Sub, time},
 };
@@ -7,7 +7,7 @@ use {
 type Frequency = f32;
 
 const A4: Frequency = 440.;
-const SAMPLE_RATE: u32 = 44_100;
+pub const SAMPLE_RATE: u32 = 44_100;
 
 #[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
 pub enum Pitch {
@@ -49,6 +49,16 @@ macro_rules! reflex {
     }
 }
 
+impl Pitch {
+    ///the pitch `steps` letters above, with the number of octaves crossed
+    pub fn step(self, steps: i32) -> (Self, i32) {
+        use Pitch::*;
+        const SCALE: [Pitch; 7] = [C, D, E, F, G, A, B];
+        let i = SCALE.iter().position(|&p| p == self).unwrap() as i32 + steps;
+        (SCALE[i.rem_euclid(7) as usize], i.div_euclid(7))
+    }
+}
+
 impl Sub for Pitch {
     type Output = i32;
 
@@ -79,9 +89,10 @@ impl Sub for Pitch {
     }
 }
 
-#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum Note {
     Note(Pitch, i32, Accidental, Duration),
+    Chord(Vec<(Pitch, i32, Accidental)>, Duration),
     Rest(Duration),
 }
 
@@ -89,62 +100,131 @@ impl Note {
     pub fn duration(&self) -> Duration {
         match *self {
             Self::Note(_, _, _, d) => d,
+            Self::Chord(_, d) => d,
             Self::Rest(d) => d,
         }
     }
-}
 
-impl IntoIterator for Note {
-    type Item = f32;
-    type IntoIter = std::iter::FromFn<Box<dyn Send + FnMut() -> Option<Self::Item>>>;
-
-    fn into_iter(self) -> Self::IntoIter {
-        let mut sample: usize = 0;
-        let sample_duration = (self.duration().as_secs() * SAMPLE_RATE as f32) as usize;
-        let envelope_duration = sample_duration as f32 * 1.0;
-        let freq = Frequency::from(self);
-        let envelope = Piano {
-            attack: time::Duration::from_millis(1).as_secs_f32() * SAMPLE_RATE as f32,
-            decay: -1e-6,
-            release: -1e-4,
-        };
+    pub fn duration_mut(&mut self) -> &mut Duration {
+        match self {
+            Self::Note(_, _, _, d) => d,
+            Self::Chord(_, d) => d,
+            Self::Rest(d) => d,
+        }
+    }
 
-        std::iter::from_fn(Box::new(move || {
-            if sample <= sample_duration {
-                let value = 2.0 * PI * freq * sample as f32 / SAMPLE_RATE as f32;
-                sample = sample.wrapping_add(1);
-                Some(envelope.apply(value, value.sin(), envelope_duration))
-            } else {
-                None
+    ///the frequencies of all sounding pitches, empty for rests
+    pub fn frequencies(&self) -> Vec<Frequency> {
+        match self {
+            Self::Note(p, oct, acc, _) => vec![frequency((*p, *oct, *acc))],
+            Self::Chord(tones, _) => tones.iter().copied().map(frequency).collect(),
+            Self::Rest(_) => vec![],
+        }
+    }
+
+    ///the written tones, empty for rests
+    pub fn tones(&self) -> Vec<(Pitch, i32, Accidental)> {
+        match self {
+            Self::Note(p, oct, acc, _) => vec![(*p, *oct, *acc)],
+            Self::Chord(tones, _) => tones.clone(),
+            Self::Rest(_) => vec![],
+        }
+    }
+
+    ///the note with every tone replaced by `f` of it
+    pub fn map_tones(
+        self,
+        mut f: impl FnMut((Pitch, i32, Accidental)) -> (Pitch, i32, Accidental),
+    ) -> Self {
+        match self {
+            Self::Note(p, oct, acc, d) => {
+                let (p, oct, acc) = f((p, oct, acc));
+                Self::Note(p, oct, acc, d)
             }
-        }))
+            Self::Chord(tones, d) => Self::Chord(tones.into_iter().map(f).collect(), d),
+            rest => rest,
+        }
     }
 }
 
+type Samples = std::iter::FromFn<Box<dyn Send + FnMut() -> Option<f32>>>;
+
+///the samples of an event, silent once its sounding length is over
+fn samples(event: Event) -> Samples {
+    let mut sample: usize = 0;
+    let sample_duration = (event.length.as_secs_f32() * SAMPLE_RATE as f32) as usize;
+    let sounding_duration = (event.sounding.as_secs_f32() * SAMPLE_RATE as f32) as usize;
+    let envelope_duration = sounding_duration as f32 * 1.0;
+    let freqs = event.note.frequencies();
+    //each frequency sweeps exponentially to its target, the last target if there are fewer
+    let targets = event.glide.clone().unwrap_or_else(|| freqs.clone());
+    let curves = freqs
+        .iter()
+        .enumerate()
+        .map(|(i, &from)| (from, *targets.get(i).or(targets.last()).unwrap_or(&from)))
+        .collect::<Vec<_>>();
+    let mut phases = vec![0f32; curves.len()];
+    let envelope = Piano {
+        attack: time::Duration::from_millis(1).as_secs_f32() * SAMPLE_RATE as f32,
+        decay: -1e-6,
+        release: -1e-4,
+    };
+    //how long a stronger or weaker attack takes to fade, in samples
+    let attack_fade = time::Duration::from_millis(50).as_secs_f32() * SAMPLE_RATE as f32;
+
+    std::iter::from_fn(Box::new(move || {
+        if sample > sample_duration {
+            return None;
+        }
+        let value = if sample <= sounding_duration {
+            let t = 2.0 * PI * sample as f32 / SAMPLE_RATE as f32;
+            let value = t * freqs.first().unwrap_or(&0.);
+            let progress = sample as f32 / sounding_duration.max(1) as f32;
+            let amp = curves
+                .iter()
+                .zip(&mut phases)
+                .map(|(&(from, to), phase)| {
+                    let amp = phase.sin();
+                    let f = from * (to / from).powf(progress);
+                    *phase = (*phase + 2.0 * PI * f / SAMPLE_RATE as f32) % (2.0 * PI);
+                    amp
+                })
+                .sum::<f32>()
+                / curves.len().max(1) as f32;
+            let attack = 1. + (event.attack - 1.) * (-(sample as f32) / attack_fade).exp();
+            event.velocity * attack * envelope.apply(value, amp, envelope_duration)
+        } else {
+            0.
+        };
+        sample = sample.wrapping_add(1);
+        Some(value)
+    }))
+}
+
+fn frequency((p, oct, acc): (Pitch, i32, Accidental)) -> Frequency {
+    //<https://pages.mtu.edu/~suits/NoteFreqCalcs.html>
+    let n = (Pitch::A - p + (oct - 4) * 12) + i32::from(acc);
+    (A4 * (2_f32).powf(n as f32 / 12.0) * 100.).round() / 100.
+}
+
+///the frequency of a note, or of the first pitch of a chord
 impl From<Note> for Frequency {
     fn from(n: Note) -> Frequency {
-        match n {
-            Note::Note(p, oct, acc, _) => {
-                //<https://pages.mtu.edu/~suits/NoteFreqCalcs.html>
-                let n = (Pitch::A - p + (oct - 4) * 12) + i32::from(acc);
-                (A4 * (2_f32).powf(n as f32 / 12.0) * 100.).round() / 100.
-            }
-            Note::Rest(_) => 0f32,
-        }
+        n.frequencies().first().copied().unwrap_or(0f32)
     }
 }
 
-pub struct Sound(<Note as IntoIterator>::IntoIter, Duration);
+pub struct Sound(Samples, time::Duration);
 
-impl From<Note> for Sound {
-    fn from(note: Note) -> Self {
-        let dur = note.duration();
-        Self(note.into_iter(), dur)
+impl From<Event> for Sound {
+    fn from(event: Event) -> Self {
+        let length = event.length;
+        Self(samples(event), length)
     }
 }
 
 impl Iterator for Sound {
-    type Item = <Note as IntoIterator>::Item;
+    type Item = f32;
     fn next(&mut self) -> Option<Self::Item> {
         self.0.next()
     }
@@ -168,29 +248,55 @@ impl Source for Sound {
 
     #[inline]
     fn total_duration(&self) -> Option<time::Duration> {
-        Some(self.1.into())
+        Some(self.1)
     }
 }
 
 #[cfg(test)]
-use {
-    crate::{duration::DurationBuilder, parse::parse},
-    Pitch::*,
-};
+use Pitch::*;
+
+#[test]
+fn test_chord() {
+    let chord = Note::Chord(
+        vec![
+            (C, 4, Accidental::Natural),
+            (E, 4, Accidental::Natural),
+            (G, 4, Accidental::Natural),
+        ],
+        Duration::new(4, 0),
+    );
+    assert_eq!(chord.frequencies(), vec![261.63, 329.63, 392.00]);
+    assert_eq!(Frequency::from(chord.clone()), 261.63);
+
+    let samples = Sound::from(Event {
+        note: chord,
+        length: time::Duration::from_millis(500),
+        sounding: time::Duration::from_millis(250),
+        velocity: 1.,
+        attack: 1.,
+        glide: None,
+        lyric: None,
+    })
+    .collect::<Vec<_>>();
+    assert_eq!(samples.len(), SAMPLE_RATE as usize / 2 + 1);
+    assert!(samples.iter().all(|s| s.abs() <= 0.5));
+    assert!(samples[SAMPLE_RATE as usize / 4 + 1..]
+        .iter()
+        .all(|&s| s == 0.));
+}
 
 #[test]
 fn test_freq() {
-    let dur = DurationBuilder::from_bpm(120);
     assert_eq!(
-        Frequency::from(Note::Note(A, 4, Accidental::Natural, dur.build(1, 0))),
+        Frequency::from(Note::Note(A, 4, Accidental::Natural, Duration::new(1, 0))),
         A4,
     );
     assert_eq!(
-        Frequency::from(Note::Note(A, 4, Accidental::Sharp, dur.build(1, 0))),
-        Frequency::from(Note::Note(B, 4, Accidental::Flat, dur.build(1, 0))),
+        Frequency::from(Note::Note(A, 4, Accidental::Sharp, Duration::new(1, 0))),
+        Frequency::from(Note::Note(B, 4, Accidental::Flat, Duration::new(1, 0))),
     );
     assert_eq!(
-        Frequency::from(Note::Note(A, 4, Accidental::Sharp, dur.build(1, 0))),
+        Frequency::from(Note::Note(A, 4, Accidental::Sharp, Duration::new(1, 0))),
         466.16_f32,
     );
     for (p, f) in [
@@ -203,10 +309,35 @@ fn test_freq() {
         (B, 493.88),
     ] {
         assert_eq!(
-            Frequency::from(Note::Note(p, 4, Accidental::Natural, dur.build(1, 0))),
+            Frequency::from(Note::Note(p, 4, Accidental::Natural, Duration::new(1, 0))),
             f,
             "Scale failed at {:?}",
             p
         );
     }
 }
+
+#[test]
+fn test_glide() {
+    let a4 = Note::Note(A, 4, Accidental::Natural, Duration::new(1, 0));
+    let samples = Sound::from(Event {
+        note: a4,
+        length: time::Duration::from_secs(1),
+        sounding: time::Duration::from_secs(1),
+        velocity: 1.,
+        attack: 1.,
+        glide: Some(vec![880.]),
+        lyric: None,
+    })
+    .collect::<Vec<_>>();
+    //the number of times the wave rises through zero in a tenth of a second
+    let cycles = |samples: &[f32]| {
+        samples
+            .windows(2)
+            .filter(|w| w[0] < 0. && w[1] >= 0.)
+            .count()
+    };
+    let tenth = SAMPLE_RATE as usize / 10;
+    assert!((43..=46).contains(&cycles(&samples[..tenth])));
+    assert!((83..=88).contains(&cycles(&samples[samples.len() - tenth..])));
+}
@@ -0,0 +1,143 @@
+use {
+    crate::{
+        accidental::Accidental,
+        duration::{Duration, Fraction},
+        key::Key,
+        note::{Note, Pitch},
+    },
+    fraction::ToPrimitive,
+};
+
+type Tone = (Pitch, i32, Accidental);
+
+///Decorates a note with auxiliary notes a step above or below it
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Ornament {
+    ///alternates with the note above
+    Trill,
+    ///plays the note below between two of the principal
+    Mordent,
+    ///plays the note above, the principal, the note below and the principal
+    Turn,
+}
+
+///the length of the short notes of ornaments and grace notes, as a fraction of a whole note
+fn short() -> Fraction {
+    Fraction::new(1u32, 32u32)
+}
+
+fn min(a: Fraction, b: Fraction) -> Fraction {
+    if a < b {
+        a
+    } else {
+        b
+    }
+}
+
+impl Ornament {
+    ///the notes played in place of `note`, the auxiliary notes being diatonic to `key`
+    pub fn expand(&self, note: &Note, key: &Key) -> Result<Vec<Note>, &'static str> {
+        let (principal, length) = match *note {
+            Note::Note(p, oct, acc, d) => ((p, oct, acc), d.fraction),
+            _ => return Err("Ornaments must be on a single note"),
+        };
+        let upper = key.step(principal.0, principal.1, 1);
+        let lower = key.step(principal.0, principal.1, -1);
+
+        let tones = match self {
+            Self::Trill => {
+                let n = (length / short()).floor().to_u32().unwrap_or(0).max(2);
+                let each = length / Fraction::new(n, 1u32);
+                (0..n)
+                    .map(|i| (if i % 2 == 0 { principal } else { upper }, each))
+                    .collect()
+            }
+            Self::Mordent => {
+                let each = min(short(), length / Fraction::new(4u32, 1u32));
+                vec![
+                    (principal, each),
+                    (lower, each),
+                    (principal, length - each - each),
+                ]
+            }
+            Self::Turn => {
+                let each = length / Fraction::new(4u32, 1u32);
+                vec![
+                    (upper, each),
+                    (principal, each),
+                    (lower, each),
+                    (principal, each),
+                ]
+            }
+        };
+        Ok(tones
+            .into_iter()
+            .map(|((p, oct, acc), f)| Note::Note(p, oct, acc, Duration::from(f)))
+            .collect::<Vec<_>>())
+    }
+}
+
+///Plays `grace` notes before `notes`, taking their time from the first of them
+pub fn grace(grace: &[Tone], mut notes: Vec<Note>) -> Vec<Note> {
+    let n = Fraction::new(grace.len() as u32, 1u32);
+    let first = notes[0].duration().fraction;
+    //grace notes take at most half of the note
+    let each = min(short(), first / n / Fraction::new(2u32, 1u32));
+    *notes[0].duration_mut() = Duration::from(first - each * n);
+    grace
+        .iter()
+        .map(|&(p, oct, acc)| Note::Note(p, oct, acc, Duration::from(each)))
+        .chain(notes)
+        .collect()
+}
+
+#[cfg(test)]
+use {crate::note::Pitch::*, std::convert::TryFrom};
+
+#[test]
+fn test_ornaments() {
+    use crate::key::Mode;
+
+    let key = Key::try_from((G, 4, Accidental::Natural, Mode::Major)).unwrap();
+    let tones = |notes: Vec<Note>| {
+        notes
+            .iter()
+            .map(|n| match n {
+                Note::Note(p, oct, acc, d) => (*p, *oct, *acc, d.fraction),
+                _ => unreachable!(),
+            })
+            .collect::<Vec<_>>()
+    };
+    let quarter = Note::Note(E, 4, Accidental::Natural, Duration::new(4, 0));
+
+    let trill = tones(Ornament::Trill.expand(&quarter, &key).unwrap());
+    assert_eq!(trill.len(), 8);
+    assert_eq!(trill[1], (F, 4, Accidental::Sharp, short()));
+
+    let mordent = tones(Ornament::Mordent.expand(&quarter, &key).unwrap());
+    assert_eq!(mordent[1].0, D);
+    assert_eq!(mordent[2].3, Fraction::new(3u32, 16u32));
+
+    let b = Note::Note(B, 4, Accidental::Natural, Duration::new(2, 0));
+    let turn = tones(Ornament::Turn.expand(&b, &key).unwrap());
+    assert_eq!(
+        turn.iter().map(|t| (t.0, t.1)).collect::<Vec<_>>(),
+        vec![(C, 5), (B, 4), (A, 4), (B, 4)]
+    );
+    assert!(Ornament::Turn
+        .expand(&Note::Rest(Duration::new(4, 0)), &key)
+        .is_err());
+
+    let graced = tones(grace(
+        &[(D, 4, Accidental::Natural), (F, 4, Accidental::Sharp)],
+        vec![Note::Note(E, 4, Accidental::Natural, Duration::new(16, 0))],
+    ));
+    assert_eq!(
+        graced.iter().map(|t| t.3).collect::<Vec<_>>(),
+        vec![
+            Fraction::new(1u32, 64u32),
+            Fraction::new(1u32, 64u32),
+            Fraction::new(1u32, 32u32)
+        ]
+    );
+}
@@ -5,19 +5,26 @@ use {
     },
     crate::{
         accidental::Accidental,
-        duration::{Duration, DurationBuilder},
+        articulation::Articulation,
+        duration::{Duration, Fraction},
         key::Key,
-        note::Note,
+        note::{Note, Pitch},
     },
     nom::{
-        character::complete::{char as parse_char, i32 as parse_i32, one_of, u32 as parse_u32},
-        combinator::opt,
-        multi::{fold_many0, many0_count, separated_list0},
-        sequence::preceded,
+        branch::alt,
+        character::complete::{
+            char as parse_char, i32 as parse_i32, multispace0, multispace1, one_of,
+            u32 as parse_u32,
+        },
+        combinator::{map, opt, value},
+        multi::{fold_many0, many0_count, separated_list0, separated_list1},
+        sequence::{delimited, pair, preceded},
         Err::Failure,
     },
 };
 
+type Tone = (Pitch, i32, Accidental);
+
 pub fn octave<'a>(default: &'a i32) -> impl 'a + FnMut(&str) -> IResult<&str, i32> {
     move |input| {
         let (input, oct) = opt(parse_i32)(input)?;
@@ -26,6 +33,24 @@ pub fn octave<'a>(default: &'a i32) -> impl 'a + FnMut(&str) -> IResult<&str, i3
     }
 }
 
+///the octaves the tone is moved by, up for each `'` and down for each `,`
+pub fn octave_marks(input: &str) -> IResult<&str, i32> {
+    fold_many0(
+        one_of("',"),
+        || 0,
+        |oct, c| if c == '\'' { oct + 1 } else { oct - 1 },
+    )(input)
+}
+
+///the octave placing `pitch` within a fourth of the `previous` tone
+fn closest_octave((previous, oct): (Pitch, i32), pitch: Pitch) -> i32 {
+    (-3..=3)
+        .map(|steps| previous.step(steps))
+        .find(|(p, _)| *p == pitch)
+        .map(|(_, octaves)| oct + octaves)
+        .unwrap()
+}
+
 pub fn accidental<'a>(
     default: &'a Accidental,
 ) -> impl 'a + FnMut(&str) -> IResult<&str, Accidental> {
@@ -49,9 +74,9 @@ pub fn accidental<'a>(
         match acc {
             (0, 0, 0) => Ok((input, *default)),
             (1, 0, 0) => Ok((input, Sharp)),
-            (n @ 2.., 0, 0) => Ok((input, NSharp(n as i32))),
+            (n @ 2.., 0, 0) => Ok((input, NSharp(n))),
             (0, 1, 0) => Ok((input, Flat)),
-            (0, n @ 2.., 0) => Ok((input, NFlat(n as i32))),
+            (0, n @ 2.., 0) => Ok((input, NFlat(n))),
             (0, 0, 1) => Ok((input, Natural)),
             (0, 0, 2..) => Err(Failure(AccidentalError("Multiple n Accidentals"))),
             _ => Err(Failure(AccidentalError("Mixed Accidentals"))),
@@ -59,46 +84,168 @@ pub fn accidental<'a>(
     }
 }
 
-pub fn duration<'a>(dur: &'a DurationBuilder) -> impl 'a + FnMut(&str) -> IResult<&str, Duration> {
-    move |input| {
-        if let (input, Some('/')) = opt(parse_char('/'))(input)? {
-            let (input, durs) = separated_list0(parse_char('~'), |input| {
-                let (input, denom) = opt(parse_u32)(input)?;
-                if let Some(denom) = denom {
-                    if !denom.is_power_of_two() {
-                        Err(Failure(DurationError("Duration must be power of two")))
-                    } else {
-                        let (input, dots) = many0_count(parse_char('.'))(input)?;
-                        Ok((input, dur.build(denom, dots as u32)))
-                    }
+pub fn duration(input: &str) -> IResult<&str, Duration> {
+    let (input, duration) = if let (input, Some('/')) = opt(parse_char('/'))(input)? {
+        let (input, durs) = separated_list0(parse_char('~'), |input| {
+            let (input, denom) = opt(parse_u32)(input)?;
+            if let Some(denom) = denom {
+                if !denom.is_power_of_two() {
+                    Err(Failure(DurationError("Duration must be power of two")))
                 } else {
-                    Ok((input, dur.build(4, 0)))
+                    let (input, dots) = many0_count(parse_char('.'))(input)?;
+                    //the last dot adds a 1/(denom * 2^dots) note
+                    if dots as u32 > denom.leading_zeros() {
+                        return Err(Failure(DurationError("Duration has too many dots")));
+                    }
+                    Ok((input, Duration::new(denom, dots as u32)))
                 }
-            })(input)?;
+            } else {
+                Ok((input, Duration::new(4, 0)))
+            }
+        })(input)?;
+
+        (input, durs.iter().sum::<Duration>())
+    } else {
+        (input, Duration::new(4, 0))
+    };
+
+    //a single triplet note
+    match opt(parse_char('t'))(input)? {
+        (input, Some(_)) => Ok((input, duration.scale(Fraction::new(2u32, 3u32)))),
+        (input, None) => Ok((input, duration)),
+    }
+}
+
+pub fn articulation(input: &str) -> IResult<&str, Articulation> {
+    alt((
+        value(Articulation::Staccato, parse_char('\'')),
+        value(Articulation::Accent, parse_char('>')),
+        value(Articulation::Tenuto, parse_char('_')),
+    ))(input)
+}
+
+///`^` with the factor a note is held for, 2 if not given
+pub fn fermata(input: &str) -> IResult<&str, Fraction> {
+    let (input, factor) = preceded(
+        parse_char('^'),
+        opt(pair(parse_u32, opt(preceded(parse_char('/'), parse_u32)))),
+    )(input)?;
+    match factor {
+        None => Ok((input, Fraction::new(2u32, 1u32))),
+        Some((n, d)) if n > 0 && d != Some(0) => Ok((input, Fraction::new(n, d.unwrap_or(1)))),
+        Some(_) => Err(Failure(DurationError("Fermata factor must be positive"))),
+    }
+}
+
+///the most notes a tuplet can have or take the time of
+const MAX_TUPLET: u32 = 64;
 
-            Ok((input, durs.iter().sum::<Duration>()))
-        } else {
-            Ok((input, dur.build(4, 0)))
+///the number of notes in a tuplet and how many notes of the same kind they take the time of.
+///If not given the latter is 3 for a duplet, three quarters of other powers of two,
+///and otherwise the largest power of two below the number of notes, e.g. 5:4 or 9:8
+pub fn tuplet(input: &str) -> IResult<&str, (u32, u32)> {
+    let (input, (p, q)) = preceded(
+        parse_char('('),
+        pair(parse_u32, opt(preceded(parse_char(':'), parse_u32))),
+    )(input)?;
+    if p < 2 {
+        return Err(Failure(DurationError(
+            "Tuplet must have at least two notes",
+        )));
+    }
+    if p > MAX_TUPLET || q > Some(MAX_TUPLET) {
+        return Err(Failure(DurationError(
+            "Tuplet must have and take the time of at most 64 notes",
+        )));
+    }
+    if q == Some(0) {
+        return Err(Failure(DurationError(
+            "Tuplet must take the time of at least one note",
+        )));
+    }
+    let q = q.unwrap_or(match p {
+        2 => 3,
+        p if p.is_power_of_two() => p / 4 * 3,
+        p => 1 << (31 - p.leading_zeros()),
+    });
+    Ok((input, (p, q)))
+}
+
+///a pitch with its octave and accidental, inferred from the key if not given,
+///and whether the accidental was written.
+///Given the `relative` tone before it, an octave not given is instead the closest one,
+///moved by octave marks, and each tone is relative to the last one parsed
+pub fn tone<'a>(
+    key: &'a Key,
+    relative: Option<(Pitch, i32)>,
+) -> impl 'a + FnMut(&str) -> IResult<&str, (Tone, bool)> {
+    let mut previous = relative;
+    move |input| {
+        let (input, pitch) = pitch(input)?;
+        let (input, oct) = match previous {
+            Some(previous) => alt((
+                parse_i32,
+                map(octave_marks, |marks| {
+                    closest_octave(previous, pitch) + marks
+                }),
+            ))(input)?,
+            None => octave(&key.oct)(input)?,
+        };
+        if previous.is_some() {
+            previous = Some((pitch, oct));
         }
+        let (rest, accidental) = accidental(key.accidental(&pitch, oct))(input)?;
+        Ok((rest, ((pitch, oct, accidental), rest.len() < input.len())))
+    }
+}
+
+///grace notes played before the principal note, with whether their accidentals were written
+pub fn grace<'a>(
+    key: &'a Key,
+    relative: Option<(Pitch, i32)>,
+) -> impl 'a + FnMut(&str) -> IResult<&str, Vec<(Tone, bool)>> {
+    move |input| {
+        delimited(
+            pair(parse_char('{'), multispace0),
+            separated_list1(multispace1, tone(key, relative)),
+            pair(multispace0, parse_char('}')),
+        )(input)
     }
 }
 
+///a note with whether its accidental was written
 pub fn note<'a>(
     key: &'a Key,
-    dur: &'a DurationBuilder,
-) -> impl 'a + FnMut(&str) -> IResult<&str, Note> {
+    relative: Option<(Pitch, i32)>,
+) -> impl 'a + FnMut(&str) -> IResult<&str, (Note, Vec<bool>)> {
     move |input| {
-        let (input, pitch) = pitch(input)?;
-        let (input, oct) = octave(&key.oct)(input)?;
-        let (input, accidental) = accidental(key.get(&pitch))(input)?;
-        let (input, duration) = duration(dur)(input)?;
-        Ok((input, Note::Note(pitch, oct, accidental, duration)))
+        let (input, ((pitch, oct, accidental), written)) = tone(key, relative)(input)?;
+        let (input, duration) = duration(input)?;
+        Ok((
+            input,
+            (Note::Note(pitch, oct, accidental, duration), vec![written]),
+        ))
     }
 }
 
-pub fn rest<'a>(dur: &'a DurationBuilder) -> impl 'a + FnMut(&str) -> IResult<&str, Note> {
+///a chord with whether the accidental of each tone was written
+pub fn chord<'a>(
+    key: &'a Key,
+    relative: Option<(Pitch, i32)>,
+) -> impl 'a + FnMut(&str) -> IResult<&str, (Note, Vec<bool>)> {
     move |input| {
-        let (input, duration) = preceded(parse_char('R'), duration(dur))(input)?;
-        Ok((input, Note::Rest(duration)))
+        let (input, tones) = delimited(
+            pair(parse_char('['), multispace0),
+            separated_list1(multispace1, tone(key, relative)),
+            pair(multispace0, parse_char(']')),
+        )(input)?;
+        let (input, duration) = duration(input)?;
+        let (tones, written) = tones.into_iter().unzip();
+        Ok((input, (Note::Chord(tones, duration), written)))
     }
 }
+
+pub fn rest(input: &str) -> IResult<&str, (Note, Vec<bool>)> {
+    let (input, duration) = preceded(parse_char('R'), duration)(input)?;
+    Ok((input, (Note::Rest(duration), Vec::new())))
+}
@@ -1,4 +1,8 @@
-use nom::error::{ErrorKind, FromExternalError, ParseError};
+use {
+    crate::{duration::Fraction, signature::TimeSignature},
+    nom::error::{ErrorKind, FromExternalError, ParseError},
+    std::fmt,
+};
 
 pub type IResult<I, O, E = ParserError<I>> = nom::IResult<I, O, E>;
 
@@ -25,3 +29,131 @@ impl<I> FromExternalError<I, &'static str> for ParserError<I> {
         Self::Other(e)
     }
 }
+
+impl<I: fmt::Display> fmt::Display for ParserError<I> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::AccidentalError(e) | Self::DurationError(e) | Self::Other(e) => {
+                write!(f, "{}", e)
+            }
+            Self::Nom(input, _) => write!(f, "Unexpected `{}`", input),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum ScoreErrorKind<'a> {
+    InvalidToken,
+    ///a note tied to a note of a different pitch
+    InvalidTie,
+    ///the actual and expected length of a measure
+    InvalidMeasure(Fraction, TimeSignature),
+    Parser(ParserError<&'a str>),
+}
+
+///An error located in the source of a score
+#[derive(Debug)]
+pub struct ScoreError<'a> {
+    ///byte offset of `token` in the source
+    pub offset: usize,
+    pub line: usize,
+    pub column: usize,
+    pub token: &'a str,
+    pub measure: usize,
+    pub kind: ScoreErrorKind<'a>,
+}
+
+impl<'a> ScoreError<'a> {
+    ///`token` must be a slice of `src`
+    pub fn new(src: &'a str, token: &'a str, measure: usize, kind: ScoreErrorKind<'a>) -> Self {
+        let offset = token.as_ptr() as usize - src.as_ptr() as usize;
+        let before = &src[..offset];
+        let line = before.matches('\n').count() + 1;
+        let column = before[before.rfind('\n').map_or(0, |n| n + 1)..]
+            .chars()
+            .count()
+            + 1;
+
+        Self {
+            offset,
+            line,
+            column,
+            token,
+            measure,
+            kind,
+        }
+    }
+}
+
+impl ScoreError<'_> {
+    fn message(&self) -> String {
+        match &self.kind {
+            ScoreErrorKind::InvalidToken => format!("Invalid token: `{}`", self.token),
+            ScoreErrorKind::InvalidTie => {
+                format!("Tied notes must have the same pitch: `{}`", self.token)
+            }
+            ScoreErrorKind::InvalidMeasure(act, exp) => {
+                format!("Invalid measure no. {}: {} ≠ {}", self.measure, act, exp)
+            }
+            ScoreErrorKind::Parser(e) => format!("{} in `{}`", e, self.token),
+        }
+    }
+
+    ///the part of `token` to underline, as a char offset and char count
+    fn span(&self) -> (usize, usize) {
+        let chars = |s: &str| s.chars().count();
+        match self.kind {
+            ScoreErrorKind::Parser(ParserError::Nom(input, _)) => {
+                let start = input.as_ptr() as usize - self.token.as_ptr() as usize;
+                match self.token.get(start..) {
+                    Some(rest) if !input.is_empty() => (chars(&self.token[..start]), chars(rest)),
+                    _ => (0, chars(self.token)),
+                }
+            }
+            _ => (0, chars(self.token)),
+        }
+    }
+
+    fn label(&self) -> &'static str {
+        match self.kind {
+            ScoreErrorKind::InvalidToken => "expected a note, rest or directive",
+            ScoreErrorKind::InvalidTie => "tied from a different pitch",
+            ScoreErrorKind::InvalidMeasure(..) => "measure ends here",
+            ScoreErrorKind::Parser(_) => "",
+        }
+    }
+
+    ///Renders the error rustc style, quoting the offending line of `src`.
+    ///`name` is the name of the score, usually its path
+    pub fn render(&self, src: &str, name: &str) -> String {
+        let line = src.lines().nth(self.line - 1).unwrap_or("");
+        let gutter = " ".repeat(self.line.to_string().len());
+        let (start, len) = self.span();
+
+        let mut underline = " ".repeat(self.column - 1 + start) + &"^".repeat(len.max(1));
+        if !self.label().is_empty() {
+            underline = underline + " " + self.label();
+        }
+
+        format!(
+            "error: {msg}\n\
+             {g}--> {name}:{l}:{c}\n\
+             {g} |\n\
+             {l} | {line}\n\
+             {g} | {underline}\n",
+            msg = self.message(),
+            g = gutter,
+            name = name,
+            l = self.line,
+            c = self.column + start,
+            line = line,
+            underline = underline,
+        )
+    }
+}
+
+impl fmt::Display for ScoreError<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} at {}:{}", self.message(), self.line, self.column)
+    }
+}
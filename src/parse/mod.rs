@@ -1,22 +1,36 @@
 use {
     crate::{
         accidental::Accidental,
-        duration::{Duration, DurationBuilder, Fraction},
+        duration::{Duration, Fraction},
+        dynamic::{Dynamic, Hairpin},
+        interval::{shift_tone, Interval},
         key::{Key, Mode},
+        lyrics::{syllables, Syllable},
         note::{Note, Pitch},
+        ornament::{self, Ornament},
+        score::{Bar, Comment, Element, Navigation, Score, ScoreMetadata, Voice},
+        signature::{TimeSignature, MAX_NUMERATOR},
+        tempo::{Gradual, Tempo},
     },
-    combinators::{accidental, note, octave, rest},
+    combinators::{accidental, articulation, chord, fermata, grace, note, octave, rest, tuplet},
     error::IResult,
     nom::{
         branch::alt,
-        bytes::complete::tag,
-        character::complete::{char as parse_char, one_of, u32 as parse_u32},
-        combinator::{all_consuming, map_res, opt},
-        sequence::{preceded, separated_pair, tuple},
+        bytes::complete::{tag, take_while, take_while1},
+        character::complete::{
+            char as parse_char, i32 as parse_i32, multispace0, multispace1, one_of,
+            u32 as parse_u32,
+        },
+        combinator::{all_consuming, cut, map, map_res, opt, value},
+        multi::{many0, separated_list0, separated_list1},
+        sequence::{delimited, pair, preceded, separated_pair, terminated, tuple},
+        Err::Failure,
     },
-    std::convert::TryFrom,
+    std::{collections::HashMap, convert::TryFrom, mem},
 };
 
+pub use error::{ParserError, ScoreError, ScoreErrorKind};
+
 mod combinators;
 mod error;
 
@@ -25,30 +39,220 @@ pub fn pitch(input: &str) -> IResult<&str, Pitch> {
 }
 
 pub fn parse_key(input: &str) -> IResult<&str, Key> {
-    let mode = map_res(opt(one_of("Mm")), |opt| Mode::try_from(opt.unwrap_or('M')));
+    let mode = map_res(take_while(|c: char| c.is_alphabetic()), Mode::try_from);
 
-    let (input, key) = map_res(
-        preceded(
-            tag("K:"),
-            tuple((pitch, octave(&4), accidental(&Accidental::Natural), mode)),
-        ),
+    let standard = map_res(
+        tuple((pitch, octave(&4), accidental(&Accidental::Natural), mode)),
         Key::try_from,
+    );
+    //explicit accidentals such as `[F# Bb]`
+    let explicit = preceded(
+        pair(parse_char('['), multispace0),
+        cut(map_res(
+            terminated(
+                separated_list0(multispace1, pair(pitch, accidental(&Accidental::Natural))),
+                pair(multispace0, parse_char(']')),
+            ),
+            |accidentals| Key::with_accidentals(4, accidentals),
+        )),
+    );
+
+    let (input, key) = preceded(
+        tag("K:"),
+        cut(alt((
+            value(Key::default(), tag("none")),
+            explicit,
+            standard,
+        ))),
     )(input)?;
 
     Ok((input, key))
 }
 
-pub fn bar_line(input: &str) -> IResult<&str, char> {
-    parse_char('|')(input)
+pub fn bar_line(input: &str) -> IResult<&str, Bar> {
+    let (input, (end_repeat, _, start_repeat)) =
+        tuple((opt(parse_char(':')), parse_char('|'), opt(parse_char(':'))))(input)?;
+    let (input, volta) = separated_list0(parse_char(','), parse_u32)(input)?;
+    Ok((
+        input,
+        Bar {
+            end_repeat: end_repeat.is_some(),
+            start_repeat: start_repeat.is_some(),
+            volta,
+        },
+    ))
+}
+
+pub fn navigation(input: &str) -> IResult<&str, Navigation> {
+    alt((
+        value(Navigation::Segno, tag("Segno")),
+        value(Navigation::Coda, tag("Coda")),
+        value(Navigation::Fine, tag("Fine")),
+        value(Navigation::DaCapo, tag("D.C.")),
+        value(Navigation::DalSegno, tag("D.S.")),
+    ))(input)
 }
 
 pub fn bpm(input: &str) -> IResult<&str, u32> {
-    preceded(tag("BPM:"), parse_u32)(input)
+    let (input, bpm) = preceded(tag("BPM:"), parse_u32)(input)?;
+    if bpm == 0 {
+        return Err(Failure(ParserError::Other("BPM must be above 0")));
+    }
+    Ok((input, bpm))
+}
+
+pub fn gradual(input: &str) -> IResult<&str, Gradual> {
+    alt((
+        value(Gradual::Accelerando, tag("accel.")),
+        value(Gradual::Ritardando, tag("rit.")),
+    ))(input)
+}
+
+pub fn dynamic(input: &str) -> IResult<&str, Dynamic> {
+    alt((
+        value(Dynamic::PP, tag("pp")),
+        value(Dynamic::P, tag("p")),
+        value(Dynamic::MP, tag("mp")),
+        value(Dynamic::MF, tag("mf")),
+        value(Dynamic::FF, tag("ff")),
+        value(Dynamic::F, tag("f")),
+    ))(input)
+}
+
+pub fn hairpin(input: &str) -> IResult<&str, Hairpin> {
+    alt((
+        value(Hairpin::Crescendo, parse_char('<')),
+        value(Hairpin::Diminuendo, parse_char('>')),
+    ))(input)
+}
+
+pub fn pickup(input: &str) -> IResult<&str, ()> {
+    value((), tag("Pickup"))(input)
+}
+
+pub fn glissando(input: &str) -> IResult<&str, ()> {
+    value((), tag("~>"))(input)
 }
 
-pub fn parse_measure(input: &str) -> IResult<&str, Fraction> {
-    let (input, f) = separated_pair(parse_u32, parse_char('/'), parse_u32)(input)?;
-    Ok((input, f.into()))
+///`(` starting a slur or `)` ending one
+pub fn slur(input: &str) -> IResult<&str, bool> {
+    alt((value(true, parse_char('(')), value(false, parse_char(')'))))(input)
+}
+
+pub fn parse_ornament(input: &str) -> IResult<&str, Ornament> {
+    alt((
+        value(Ornament::Trill, tag("tr")),
+        value(Ornament::Mordent, tag("mord")),
+        value(Ornament::Turn, tag("turn")),
+    ))(input)
+}
+
+///whether accidentals carry through the measure, or only apply to their own note
+pub fn accidental_scope(input: &str) -> IResult<&str, bool> {
+    preceded(
+        tag("Accidentals:"),
+        alt((value(true, tag("measure")), value(false, tag("note")))),
+    )(input)
+}
+
+///whether octaves not given are relative to the previous note, or the octave of the key
+pub fn octave_mode(input: &str) -> IResult<&str, bool> {
+    preceded(
+        tag("Octave:"),
+        alt((value(true, tag("relative")), value(false, tag("absolute")))),
+    )(input)
+}
+
+///`Transpose:` with a number of half steps, e.g. `+2`, or an interval, e.g. `M2` or `-m3`
+pub fn transposition(input: &str) -> IResult<&str, Interval> {
+    let interval = map_res(pair(one_of("PMmAd"), parse_u32), Interval::try_from);
+    preceded(
+        tag("Transpose:"),
+        alt((
+            map_res(parse_i32, Interval::try_from),
+            map(pair(opt(parse_char('-')), interval), |(down, interval)| {
+                if down.is_some() {
+                    -interval
+                } else {
+                    interval
+                }
+            }),
+        )),
+    )(input)
+}
+
+pub fn parse_voice(input: &str) -> IResult<&str, &str> {
+    preceded(tag("V:"), take_while1(|c: char| !c.is_whitespace()))(input)
+}
+
+pub fn metadata(input: &str) -> IResult<&str, (char, &str)> {
+    let (input, field) = terminated(one_of("TCA©"), parse_char(':'))(input)?;
+    Ok(("", (field, input.trim())))
+}
+
+pub fn lyrics(input: &str) -> IResult<&str, &str> {
+    let (input, _) = tag("W:")(input)?;
+    Ok(("", input))
+}
+
+fn macro_name(input: &str) -> IResult<&str, &str> {
+    preceded(
+        parse_char('@'),
+        take_while1(|c: char| c.is_alphanumeric() || c == '_'),
+    )(input)
+}
+
+///`@name = ...` with the name and the tokens it stands for
+pub fn macro_definition(input: &str) -> IResult<&str, (&str, &str)> {
+    let (input, name) = terminated(macro_name, pair(multispace0, parse_char('=')))(input)?;
+    Ok(("", (name, input.trim())))
+}
+
+///`@name` with the number of half steps to transpose by, if given as `@name^n`
+pub fn macro_reference(input: &str) -> IResult<&str, (&str, i32)> {
+    let (input, name) = macro_name(input)?;
+    let semitones = map_res(parse_i32, |n| Interval::try_from(n).map(|_| n));
+    let (input, semitones) = opt(preceded(parse_char('^'), semitones))(input)?;
+    Ok((input, (name, semitones.unwrap_or(0))))
+}
+
+pub fn beat_groups(input: &str) -> IResult<&str, Vec<u32>> {
+    delimited(
+        parse_char('('),
+        separated_list1(parse_char('+'), parse_u32),
+        parse_char(')'),
+    )(input)
+}
+
+pub fn parse_measure(input: &str) -> IResult<&str, TimeSignature> {
+    let (input, (n, d)) = separated_pair(parse_u32, parse_char('/'), parse_u32)(input)?;
+    if n > MAX_NUMERATOR {
+        return Err(Failure(ParserError::Other(
+            "Signature must have at most 64 notes per measure",
+        )));
+    }
+    if !d.is_power_of_two() {
+        return Err(Failure(ParserError::Other(
+            "Signature must count notes whose length is a power of two",
+        )));
+    }
+    let signature = TimeSignature::new(n, d);
+    match opt(beat_groups)(input)? {
+        (input, Some(groups)) => match signature.with_groups(groups) {
+            Ok(signature) => Ok((input, signature)),
+            Err(e) => Err(Failure(ParserError::Other(e))),
+        },
+        (input, None) => Ok((input, signature)),
+    }
+}
+
+#[derive(Copy, Clone)]
+enum Pickup {
+    None,
+    ///the first measure is a pickup
+    Pending,
+    ///the length of the pickup, the last measure may make up the rest
+    Length(Fraction),
 }
 
 enum Valid {
@@ -56,15 +260,17 @@ enum Valid {
     Invalid(Fraction),
 }
 
-fn validate_measure(measure: &(Fraction, Vec<Note>)) -> Valid {
-    if !measure.1.is_empty() {
-        let f = measure
-            .1
-            .iter()
-            .map(|&v| v.duration())
-            .sum::<Duration>()
-            .fraction;
-        if f == measure.0 {
+fn validate_measure(signature: &TimeSignature, measure: &[Element]) -> Valid {
+    let notes = measure
+        .iter()
+        .filter_map(|e| match e {
+            Element::Note(n) => Some(n.duration()),
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+    if !notes.is_empty() {
+        let f = notes.iter().sum::<Duration>().fraction;
+        if f == signature.length() {
             Valid::Valid
         } else {
             Valid::Invalid(f)
@@ -74,62 +280,1434 @@ fn validate_measure(measure: &(Fraction, Vec<Note>)) -> Valid {
     }
 }
 
-macro_rules! validate_measure {
-    ($out:ident, $measure:ident) => {
-        if let Valid::Invalid(act) = validate_measure(&$measure) {
-            panic!(
-                "Invalid measure no. {}. {} ≠ {}",
-                $out.len(),
-                act,
-                $measure.0
-            );
-        }
-        if !$measure.1.is_empty() {
-            $out.push($measure.1);
-            $measure.1 = vec![];
+///where relative octave mode starts from
+const MIDDLE_C: (Pitch, i32) = (Pitch::C, 4);
+
+struct VoiceState<'a> {
+    name: &'a str,
+    elements: Vec<Element>,
+    signature: TimeSignature,
+    ///index of the first element of the current measure
+    measure_start: usize,
+    ///the number of finished measures
+    measures: usize,
+    key: Key,
+    ///whether written accidentals carry to later notes of the measure
+    carry_accidentals: bool,
+    ///in relative octave mode, the first tone of the last note
+    relative: Option<(Pitch, i32)>,
+    ///the interval notes are transposed by
+    interval: Option<Interval>,
+    bpm: u32,
+    ///the ratio, number of notes left and token of the current tuplet
+    tuplet: Option<(Fraction, u32, &'a str)>,
+    ///the last note as written and as played and its token, if tied to the next
+    tie: Option<(Note, Note, &'a str)>,
+    pickup: Pickup,
+    ///an accel. or rit. waiting for the BPM it leads to
+    gradual: Option<(Gradual, &'a str)>,
+    dynamic: Dynamic,
+    ///a hairpin waiting for the dynamic it leads to
+    hairpin: Option<(Hairpin, &'a str)>,
+    ///the `(` starting the current slur
+    slur: Option<&'a str>,
+    ///an ornament waiting for its note
+    ornament: Option<(Ornament, &'a str)>,
+    ///a glissando waiting for the note it slides to
+    glissando: Option<&'a str>,
+    ///a measure making up the rest of the pickup, invalid unless no notes follow
+    short: Option<ScoreError<'a>>,
+    ///the line of the last notes and the indices of those that can be given a syllable
+    lyric_notes: (usize, Vec<usize>, u32),
+}
+
+impl<'a> VoiceState<'a> {
+    fn new(name: &'a str, signature: TimeSignature, key: Key, bpm: u32) -> Self {
+        Self {
+            name,
+            elements: Vec::new(),
+            signature,
+            measure_start: 0,
+            measures: 0,
+            key,
+            carry_accidentals: true,
+            relative: None,
+            interval: None,
+            bpm,
+            tuplet: None,
+            tie: None,
+            pickup: Pickup::None,
+            gradual: None,
+            dynamic: Dynamic::default(),
+            hairpin: None,
+            slur: None,
+            ornament: None,
+            glissando: None,
+            short: None,
+            lyric_notes: (0, Vec::new(), 0),
         }
-    };
+    }
+
+    fn measure_has_notes(&self) -> bool {
+        self.elements[self.measure_start..]
+            .iter()
+            .any(|e| matches!(e, Element::Note(_)))
+    }
+
+    ///BPM counts the beat of the signature, the tempo map changing the beat at the signature
+    fn set_signature(&mut self, signature: TimeSignature) {
+        self.elements.push(Element::Signature(signature.clone()));
+        self.signature = signature;
+    }
 }
 
-pub fn parse(txt: &str) -> Vec<Note> {
-    let mut out = Vec::new();
-    let mut measure = (Fraction::new(4u32, 4u32), Vec::new());
+///the most tokens a macro reference may expand to, including those of nested references
+const MAX_EXPANSION: usize = 10_000;
 
-    let mut key = Key::default();
-    let mut dur = DurationBuilder::from_bpm(120);
+struct Parser<'a> {
+    src: &'a str,
+    metadata: ScoreMetadata,
+    voices: Vec<VoiceState<'a>>,
+    ///index of the voice currently being written
+    current: usize,
+    ///errors found besides the one returned for a token, such as a measure found invalid later
+    errors: Vec<ScoreError<'a>>,
+    ///the `/*` of a block comment never ended with `*/`
+    unclosed_comment: Option<&'a str>,
+    ///the line of the token being parsed, that of the reference within macros
+    line: usize,
+    ///the tokens each macro stands for
+    macros: HashMap<&'a str, &'a str>,
+    ///the macros being expanded, innermost last
+    expanding: Vec<&'a str>,
+    ///the tokens expanded so far from the outermost macro reference
+    expanded: usize,
+    ///the half steps notes are transposed by in the macros being expanded
+    transpose: i32,
+}
 
-    for token in txt.split_whitespace() {
-        if let Ok(("", note)) = all_consuming(alt((note(&key, &dur), rest(&dur))))(token) {
-            measure.1.push(note);
-            continue;
+impl<'a> Parser<'a> {
+    fn new(src: &'a str) -> Self {
+        Self {
+            src,
+            metadata: ScoreMetadata::default(),
+            voices: vec![VoiceState::new(
+                "1",
+                TimeSignature::default(),
+                Key::default(),
+                120,
+            )],
+            current: 0,
+            errors: Vec::new(),
+            unclosed_comment: None,
+            line: 0,
+            macros: HashMap::new(),
+            expanding: Vec::new(),
+            expanded: 0,
+            transpose: 0,
         }
-        if let Ok(("", new_key)) = all_consuming(parse_key)(token) {
-            key = new_key;
-            continue;
+    }
+
+    fn voice(&mut self) -> &mut VoiceState<'a> {
+        &mut self.voices[self.current]
+    }
+
+    fn error(&self, token: &'a str, kind: ScoreErrorKind<'a>) -> ScoreError<'a> {
+        ScoreError::new(self.src, token, self.voices[self.current].measures, kind)
+    }
+
+    ///validates the current measure, `token` being the one that closed it.
+    ///A measure making up the rest of the pickup is only valid if no notes follow it
+    fn close_measure(&mut self, token: &'a str) -> Result<(), ScoreError<'a>> {
+        let voice = &self.voices[self.current];
+        let measure = &voice.elements[voice.measure_start..];
+        let length = voice.signature.length();
+        let has_notes = voice.measure_has_notes();
+        let result = match (validate_measure(&voice.signature, measure), voice.pickup) {
+            (Valid::Valid, _) => Ok(None),
+            (Valid::Invalid(act), Pickup::Pending) if act < length => {
+                self.voice().pickup = Pickup::Length(act);
+                Ok(None)
+            }
+            (Valid::Invalid(act), pickup) => {
+                let signature = voice.signature.clone();
+                let e = self.error(token, ScoreErrorKind::InvalidMeasure(act, signature));
+                match pickup {
+                    Pickup::Length(p) if act + p == length => Ok(Some(e)),
+                    _ => Err(e),
+                }
+            }
+        };
+        let voice = self.voice();
+        if has_notes {
+            if let Some(e) = voice.short.take() {
+                self.errors.push(e);
+            }
         }
-        if let Ok(("", '|')) = all_consuming(bar_line)(token) {
-            validate_measure!(out, measure);
-            continue;
+        if let Some((_, _, start)) = self.voice().tuplet.take() {
+            let e = ParserError::Other("Tuplet must end within its measure");
+            let e = self.error(start, ScoreErrorKind::Parser(e));
+            self.errors.push(e);
         }
-        if let Ok(("", bpm)) = all_consuming(bpm)(token) {
-            validate_measure!(out, measure);
-            dur = DurationBuilder::from_bpm(bpm);
-            continue;
+        let voice = self.voice();
+        let result = result.map(|short| {
+            voice.short = short.or_else(|| voice.short.take());
+        });
+        if let Pickup::Pending = voice.pickup {
+            voice.pickup = Pickup::None;
+        }
+        if has_notes {
+            voice.measures += 1;
+        }
+        voice.measure_start = voice.elements.len();
+        voice.key.end_measure();
+        result
+    }
+
+    ///Gives the syllables of a lyric line to the notes of the last line with notes in the current voice.
+    ///Each further lyric line for the same notes is the next verse
+    fn add_lyrics(&mut self, token: &'a str, line: &str) -> Result<(), ScoreError<'a>> {
+        let syllables = syllables(line);
+        if syllables.len() > self.voices[self.current].lyric_notes.1.len() {
+            return Err(self.error(
+                token,
+                ScoreErrorKind::Parser(ParserError::Other("More syllables than notes")),
+            ));
+        }
+        let voice = self.voice();
+        let (_, notes, verses) = &mut voice.lyric_notes;
+        let verse = *verses;
+        *verses += 1;
+        //each inserted syllable moves the following notes along
+        let mut inserted = 0;
+        for (i, index) in notes.iter_mut().enumerate() {
+            *index += inserted;
+            if let Some(Some(syllable)) = syllables.get(i) {
+                let at = *index + 1;
+                let syllable = Syllable {
+                    verse,
+                    ..syllable.clone()
+                };
+                voice.elements.insert(at, Element::Lyric(syllable));
+                if at <= voice.measure_start {
+                    voice.measure_start += 1;
+                }
+                inserted += 1;
+            }
+        }
+        Ok(())
+    }
+
+    ///starts a slur at `token`, or ends the current one
+    fn slur(&mut self, token: &'a str, start: bool) -> Result<(), ScoreError<'a>> {
+        let voice = self.voice();
+        let result = match (start, voice.slur) {
+            (true, None) => {
+                voice.slur = Some(token);
+                voice.elements.push(Element::SlurStart);
+                Ok(())
+            }
+            (false, Some(_)) => {
+                voice.slur = None;
+                voice.elements.push(Element::SlurEnd);
+                Ok(())
+            }
+            (true, Some(_)) => Err("Slurs cannot be nested"),
+            (false, None) => Err("Slur ended without being started"),
+        };
+        result.map_err(|e| self.error(token, ScoreErrorKind::Parser(ParserError::Other(e))))
+    }
+
+    ///Parses the tokens of macro `name` in place of `token`, transposed by `semitones`.
+    ///Errors found in them are added to `errors` in order
+    fn expand(
+        &mut self,
+        token: &'a str,
+        name: &'a str,
+        semitones: i32,
+    ) -> Result<(), ScoreError<'a>> {
+        let other = |e| ScoreErrorKind::Parser(ParserError::Other(e));
+        let body = match self.macros.get(name) {
+            None => return Err(self.error(token, other("Undefined macro"))),
+            Some(_) if self.expanding.contains(&name) => {
+                return Err(self.error(token, other("Macro refers to itself")))
+            }
+            Some(body) => *body,
+        };
+        let body = tokens(body).0;
+        if self.expanding.is_empty() {
+            self.expanded = 0;
+        }
+        self.expanded += body.len();
+        if self.expanded > MAX_EXPANSION {
+            return Err(self.error(token, other("Macro expands to too many tokens")));
+        }
+
+        self.expanding.push(name);
+        self.transpose += semitones;
+        for (_, token) in body {
+            if let Err(e) = self.token(token) {
+                self.errors.push(e);
+            }
+            //the reference that went over the limit has been reported
+            if self.expanded > MAX_EXPANSION {
+                break;
+            }
+        }
+        self.transpose -= semitones;
+        self.expanding.pop();
+        Ok(())
+    }
+
+    ///switches to the voice named `name`, declaring it if needed.
+    ///New voices start with the key, BPM, signature, accidental scope,
+    ///octave mode and transposition of the voice they were declared in
+    fn switch_voice(&mut self, name: &'a str) {
+        self.current = match self.voices.iter().position(|v| v.name == name) {
+            Some(i) => i,
+            None => {
+                let voice = &self.voices[self.current];
+                let mut key = voice.key.clone();
+                key.end_measure();
+                let (carry_accidentals, relative, interval) =
+                    (voice.carry_accidentals, voice.relative, voice.interval);
+                let mut voice = VoiceState::new(name, voice.signature.clone(), key, voice.bpm);
+                voice.carry_accidentals = carry_accidentals;
+                voice.relative = relative.map(|_| MIDDLE_C);
+                voice.interval = interval;
+                self.voices.push(voice);
+                self.voices.len() - 1
+            }
+        };
+    }
+
+    fn token(&mut self, token: &'a str) -> Result<(), ScoreError<'a>> {
+        let voice = &self.voices[self.current];
+        let note = all_consuming(tuple((
+            opt(parse_char('(')),
+            opt(grace(&voice.key, voice.relative)),
+            alt((
+                note(&voice.key, voice.relative),
+                chord(&voice.key, voice.relative),
+                rest,
+            )),
+            tuple((many0(articulation), opt(fermata), opt(parse_char('-')))),
+            opt(parse_char(')')),
+        )))(token);
+        if let Ok((
+            "",
+            (slur_start, grace, (mut note, written), (articulations, fermata, tie), slur_end),
+        )) = note
+        {
+            if slur_start.is_some() {
+                self.slur(token, true)?;
+            }
+            let transpose = self.transpose;
+            let voice = self.voice();
+            //a tone tied over keeps its accidental unless one is written, even into the next measure,
+            //and written accidentals carry through the measure
+            let tied = voice.tie.as_ref().map(|(from, _, _)| from.tones());
+            let grace: Option<(Vec<_>, Vec<_>)> = grace.map(|grace| grace.into_iter().unzip());
+            let mut carried = grace
+                .iter()
+                .flat_map(|(tones, written)| tones.iter().zip(written))
+                .filter(|(_, &written)| written)
+                .map(|(&tone, _)| tone)
+                .collect::<Vec<_>>();
+            let mut written = written.into_iter();
+            note = note.map_tones(|(p, oct, acc)| {
+                let tied = tied.iter().flatten().find(|t| (t.0, t.1) == (p, oct));
+                match (written.next().unwrap_or(false), tied) {
+                    (true, _) => {
+                        carried.push((p, oct, acc));
+                        (p, oct, acc)
+                    }
+                    (false, Some(&tied)) => tied,
+                    (false, None) => (p, oct, acc),
+                }
+            });
+            let grace = grace.map(|(tones, _)| tones);
+            if voice.carry_accidentals {
+                carried.into_iter().for_each(|tone| voice.key.carry(tone));
+            }
+            if voice.relative.is_some() {
+                if let Some(&(p, oct, _)) = note.tones().first() {
+                    voice.relative = Some((p, oct));
+                }
+            }
+            let written = note.clone();
+            //macros and the Transpose: directive together move notes to the key they lead to
+            let shift = Some(Interval::Semitones(transpose)).filter(|_| transpose != 0);
+            let interval = match (shift, voice.interval) {
+                (Some(shift), Some(interval)) => Some(shift + interval),
+                (shift, interval) => shift.or(interval),
+            };
+            let (steps, semitones) = interval.map_or((0, 0), |i| i.diatonic(&voice.key));
+            note = note.map_tones(|t| shift_tone(t, (steps, semitones)));
+            let grace = grace.map(|grace| {
+                grace
+                    .into_iter()
+                    .map(|t| shift_tone(t, (steps, semitones)))
+                    .collect::<Vec<_>>()
+            });
+            if let Some((ratio, left, start)) = voice.tuplet {
+                *note.duration_mut() = note.duration().scale(ratio);
+                voice.tuplet = Some((ratio, left - 1, start)).filter(|t| t.1 > 0);
+            }
+            let ornamented = match voice.ornament.take() {
+                Some((ornament, _)) => {
+                    let key = voice.key.transpose(Interval::Diatonic { steps, semitones });
+                    ornament.expand(&note, &key).map(Some)
+                }
+                None => Ok(None),
+            };
+            let ornamented = match (grace, ornamented) {
+                (Some(grace), Ok(notes)) => {
+                    let notes = notes.unwrap_or_else(|| vec![note.clone()]);
+                    Ok(Some(ornament::grace(&grace, notes)))
+                }
+                (_, ornamented) => ornamented,
+            };
+            match ornamented {
+                Ok(Some(notes)) => voice.elements.push(Element::Ornament(notes)),
+                Ok(None) => {}
+                Err(e) => {
+                    return Err(self.error(token, ScoreErrorKind::Parser(ParserError::Other(e))))
+                }
+            }
+            let voice = self.voice();
+            if voice.glissando.take().is_some() && matches!(note, Note::Rest(_)) {
+                return Err(self.error(
+                    token,
+                    ScoreErrorKind::Parser(ParserError::Other("Glissandos must lead to a note")),
+                ));
+            }
+            let tied_from = voice.tie.take();
+            //rests and notes tied from the previous one are not sung to a new syllable
+            if tied_from.is_none() && !matches!(note, Note::Rest(_)) {
+                let line = self.line;
+                let voice = self.voice();
+                if voice.lyric_notes.0 != line {
+                    voice.lyric_notes = (line, Vec::new(), 0);
+                }
+                let index = voice.elements.len();
+                voice.lyric_notes.1.push(index);
+            }
+            let voice = self.voice();
+            voice.elements.push(Element::Note(note.clone()));
+            voice
+                .elements
+                .extend(articulations.into_iter().map(Element::Articulation));
+            if let Some(factor) = fermata {
+                voice.elements.push(Element::Fermata(factor));
+            }
+            if tie.is_some() {
+                if let Note::Rest(_) = note {
+                    let e = ParserError::Other("Rests cannot be tied");
+                    return Err(self.error(token, ScoreErrorKind::Parser(e)));
+                }
+                voice.elements.push(Element::Tie);
+                voice.tie = Some((written, note.clone(), token));
+            }
+            if let Some((_, from, _)) = tied_from {
+                if from.frequencies() != note.frequencies() {
+                    return Err(self.error(token, ScoreErrorKind::InvalidTie));
+                }
+            }
+            return match slur_end {
+                Some(_) => self.slur(token, false),
+                None => Ok(()),
+            };
+        }
+        let tuplet = all_consuming(tuplet)(token);
+        if let Ok(("", (p, q))) = tuplet {
+            self.voice().tuplet = Some((Fraction::new(q, p), p, token));
+            return Ok(());
+        }
+        let key = all_consuming(parse_key)(token);
+        if let Ok(("", new_key)) = key {
+            self.voice().key = new_key;
+            return Ok(());
         }
-        if let Ok(("", signature)) = all_consuming(parse_measure)(token) {
-            validate_measure!(out, measure);
-            measure.0 = signature;
+        if let Ok(("", bar)) = all_consuming(bar_line)(token) {
+            let result = self.close_measure(token);
+            self.voice().elements.push(Element::Bar(bar));
+            self.voice().measure_start += 1;
+            return result;
+        }
+        if let Ok(("", ())) = all_consuming(pickup)(token) {
+            let voice = self.voice();
+            if voice.measures > 0 || voice.measure_has_notes() {
+                return Err(self.error(
+                    token,
+                    ScoreErrorKind::Parser(ParserError::Other(
+                        "Pickup must be given before the first measure",
+                    )),
+                ));
+            }
+            voice.pickup = Pickup::Pending;
+            return Ok(());
+        }
+        if let Ok(("", carry)) = all_consuming(accidental_scope)(token) {
+            self.voice().carry_accidentals = carry;
+            return Ok(());
+        }
+        if let Ok(("", interval)) = all_consuming(transposition)(token) {
+            self.voice().interval = Some(interval);
+            return Ok(());
+        }
+        if let Ok(("", relative)) = all_consuming(octave_mode)(token) {
+            self.voice().relative = Some(MIDDLE_C).filter(|_| relative);
+            return Ok(());
+        }
+        if let Ok(("", nav)) = all_consuming(navigation)(token) {
+            self.voice().elements.push(Element::Navigation(nav));
+            return Ok(());
+        }
+        let tempo = all_consuming(bpm)(token);
+        if let Ok(("", bpm)) = tempo {
+            let result = self.close_measure(token);
+            let voice = self.voice();
+            let wrong_way = match voice.gradual.take() {
+                Some((Gradual::Accelerando, _)) if bpm <= voice.bpm => {
+                    Some("accel. must lead to a faster BPM")
+                }
+                Some((Gradual::Ritardando, _)) if bpm >= voice.bpm => {
+                    Some("rit. must lead to a slower BPM")
+                }
+                _ => None,
+            };
+            let result = match wrong_way {
+                Some(e) => result.and(Err(
+                    self.error(token, ScoreErrorKind::Parser(ParserError::Other(e)))
+                )),
+                None => result,
+            };
+            let voice = self.voice();
+            voice.bpm = bpm;
+            let tempo = Tempo::new(bpm, voice.signature.beat());
+            voice.elements.push(Element::Tempo(tempo));
+            return result;
+        }
+        if let Ok(("", change)) = all_consuming(gradual)(token) {
+            let voice = self.voice();
+            voice.elements.push(Element::Gradual(change));
+            voice.gradual = Some((change, token));
+            return Ok(());
+        }
+        if let Ok(("", dynamic)) = all_consuming(dynamic)(token) {
+            let voice = self.voice();
+            let wrong_way = match voice.hairpin.take() {
+                Some((Hairpin::Crescendo, _)) if dynamic <= voice.dynamic => {
+                    Some("< must lead to a louder dynamic")
+                }
+                Some((Hairpin::Diminuendo, _)) if dynamic >= voice.dynamic => {
+                    Some("> must lead to a softer dynamic")
+                }
+                _ => None,
+            };
+            voice.dynamic = dynamic;
+            voice.elements.push(Element::Dynamic(dynamic));
+            return match wrong_way {
+                Some(e) => Err(self.error(token, ScoreErrorKind::Parser(ParserError::Other(e)))),
+                None => Ok(()),
+            };
+        }
+        if let Ok(("", change)) = all_consuming(hairpin)(token) {
+            let voice = self.voice();
+            voice.elements.push(Element::Hairpin(change));
+            voice.hairpin = Some((change, token));
+            return Ok(());
+        }
+        if let Ok(("", ornament)) = all_consuming(parse_ornament)(token) {
+            self.voice().ornament = Some((ornament, token));
+            return Ok(());
+        }
+        if let Ok(("", ())) = all_consuming(glissando)(token) {
+            let voice = self.voice();
+            let from = voice.elements.iter().rev().find_map(|e| match e {
+                Element::Note(n) => Some(n),
+                _ => None,
+            });
+            if !matches!(from, Some(Note::Note(..)) | Some(Note::Chord(..))) {
+                return Err(self.error(
+                    token,
+                    ScoreErrorKind::Parser(ParserError::Other("Glissandos must start from a note")),
+                ));
+            }
+            voice.elements.push(Element::Glissando);
+            voice.glissando = Some(token);
+            return Ok(());
+        }
+        if let Ok(("", start)) = all_consuming(slur)(token) {
+            return self.slur(token, start);
+        }
+        let signature = all_consuming(parse_measure)(token);
+        if let Ok(("", signature)) = signature {
+            let result = self.close_measure(token);
+            self.voice().set_signature(signature);
+            return result;
+        }
+        if let Ok(("", groups)) = all_consuming(beat_groups)(token) {
+            let voice = self.voice();
+            return match voice.signature.clone().with_groups(groups) {
+                Ok(signature) => {
+                    voice.set_signature(signature);
+                    Ok(())
+                }
+                Err(e) => Err(self.error(token, ScoreErrorKind::Parser(ParserError::Other(e)))),
+            };
+        }
+        if let Ok(("", name)) = all_consuming(parse_voice)(token) {
+            self.switch_voice(name);
+            return Ok(());
+        }
+        if let Ok(("", (name, body))) = macro_definition(token) {
+            self.macros.insert(name, body);
+            return Ok(());
+        }
+        if let Ok(("", (name, semitones))) = all_consuming(macro_reference)(token) {
+            return self.expand(token, name, semitones);
+        }
+        //directive values are read without their block comments, the token locating errors
+        let (value, comments, _) = strip_comments(token);
+        if let Ok(("", line)) = lyrics(&value) {
+            return self.add_lyrics(token, line);
+        }
+        if let Ok(("", (field, text))) = metadata(&value) {
+            let text = if comments.is_empty() {
+                text.to_string()
+            } else {
+                text.split_whitespace().collect::<Vec<_>>().join(" ")
+            };
+            let field = match field {
+                'T' => &mut self.metadata.title,
+                'C' => &mut self.metadata.composer,
+                'A' => &mut self.metadata.arranger,
+                _ => &mut self.metadata.copyright,
+            };
+            match field {
+                Some(field) => *field = format!("{}\n{}", field, text),
+                None => *field = Some(text),
+            }
+            return Ok(());
+        }
+
+        match (note, key, tempo, tuplet, signature) {
+            (Err(Failure(e)), ..)
+            | (_, Err(Failure(e)), ..)
+            | (_, _, Err(Failure(e)), ..)
+            | (_, _, _, Err(Failure(e)), _)
+            | (.., Err(Failure(e))) => Err(self.error(token, ScoreErrorKind::Parser(e))),
+            _ if token.matches(['[', '{']).count() > token.matches([']', '}']).count() => {
+                let e = ParserError::Other("Brackets must be closed");
+                Err(self.error(token, ScoreErrorKind::Parser(e)))
+            }
+            _ => Err(self.error(token, ScoreErrorKind::InvalidToken)),
+        }
+    }
+
+    ///closes the last measure of every voice, pointing any error just past the last token.
+    ///An accel., rit. or hairpin never given its target, a slur or tie never ended
+    ///or an ornament or glissando without a note is also an error
+    fn end(&mut self) -> Vec<ScoreError<'a>> {
+        let end = self.src.trim_end().len();
+        let comment = self.unclosed_comment.map(|token| {
+            let e = "Block comments must be ended with `*/`";
+            self.error(token, ScoreErrorKind::Parser(ParserError::Other(e)))
+        });
+        let voices = (0..self.voices.len())
+            .flat_map(|i| {
+                self.current = i;
+                let voice = &self.voices[i];
+                let unfinished = [
+                    voice
+                        .gradual
+                        .map(|(_, token)| (token, "accel. and rit. must be followed by a BPM")),
+                    voice
+                        .hairpin
+                        .map(|(_, token)| (token, "Hairpins must be followed by a dynamic")),
+                    voice
+                        .slur
+                        .map(|token| (token, "Slurs must be ended with `)`")),
+                    voice
+                        .ornament
+                        .map(|(_, token)| (token, "Ornaments must be followed by a note")),
+                    voice
+                        .glissando
+                        .map(|token| (token, "Glissandos must be followed by a note")),
+                    voice
+                        .tie
+                        .as_ref()
+                        .map(|&(_, _, token)| (token, "Ties must be followed by a note")),
+                ];
+                let unfinished = unfinished
+                    .iter()
+                    .flatten()
+                    .map(|&(token, e)| {
+                        self.error(token, ScoreErrorKind::Parser(ParserError::Other(e)))
+                    })
+                    .collect::<Vec<_>>();
+                let closed = self.close_measure(&self.src[end..end]).err();
+                let found = mem::take(&mut self.errors);
+                unfinished.into_iter().chain(found).chain(closed)
+            })
+            .collect::<Vec<_>>();
+        voices.into_iter().chain(comment).collect()
+    }
+
+    ///voices without any notes are left out
+    fn into_score(self, comments: Vec<Comment>) -> Score {
+        Score {
+            metadata: self.metadata,
+            comments,
+            voices: self
+                .voices
+                .into_iter()
+                .filter(|v| v.elements.iter().any(|e| matches!(e, Element::Note(_))))
+                .map(|v| Voice {
+                    name: v.name.to_string(),
+                    elements: v.elements,
+                })
+                .collect(),
+        }
+    }
+}
+
+///Blanks out `%` and `//` line comments and `/* */` block comments,
+///keeping the byte offsets of everything else.
+///A block comment never ended runs to the end of the text and its `/*` is returned
+fn strip_comments(txt: &str) -> (String, Vec<Comment>, Option<&str>) {
+    let mut stripped = txt.as_bytes().to_vec();
+    let mut comments = Vec::new();
+    let mut unclosed = None;
+    let mut i = 0;
+
+    while i < txt.len() {
+        let rest = &txt[i..];
+        let (start, end, block) = if rest.starts_with("/*") {
+            (2, rest.find("*/").map_or(rest.len(), |e| e + 2), true)
+        } else if rest.starts_with("//") || rest.starts_with('%') {
+            let start = if rest.starts_with('%') { 1 } else { 2 };
+            (start, rest.find('\n').unwrap_or(rest.len()), false)
+        } else {
+            i += rest.chars().next().map_or(1, char::len_utf8);
             continue;
+        };
+
+        let text_end = if block && rest[..end].ends_with("*/") {
+            end - 2
+        } else {
+            if block {
+                unclosed = Some(&rest[..2]);
+            }
+            end
+        };
+        comments.push(Comment {
+            offset: i,
+            text: rest[start..text_end.max(start)].to_string(),
+            block,
+        });
+        for b in &mut stripped[i..i + end] {
+            if *b != b'\n' {
+                *b = b' ';
+            }
+        }
+        i += end;
+    }
+
+    (String::from_utf8(stripped).unwrap(), comments, unclosed)
+}
+
+///directives whose value runs to the end of the line
+const LINE_DIRECTIVES: [&str; 5] = ["T:", "C:", "A:", "©:", "W:"];
+
+///Splits a score on whitespace, keeping bracketed groups such as chords and grace notes
+///and directives in `LINE_DIRECTIVES` or macro definitions whole. Comments are returned separately,
+///along with the `/*` of a block comment never ended
+fn tokens(txt: &str) -> (Vec<(usize, &str)>, Vec<Comment>, Option<&str>) {
+    let (stripped, comments, unclosed) = strip_comments(txt);
+    let mut tokens = Vec::new();
+    let mut rest = stripped.trim_start();
+    let mut lines = stripped[..stripped.len() - rest.len()]
+        .matches('\n')
+        .count();
+
+    while !rest.is_empty() {
+        let line = &rest[..rest.find('\n').unwrap_or(rest.len())];
+        let end = if LINE_DIRECTIVES.iter().any(|d| rest.starts_with(d))
+            || macro_definition(line).is_ok()
+        {
+            line.trim_end().len()
+        } else {
+            //a bracket left open ends at the line or the next bracket
+            let mut depth = 0;
+            rest.find(|c: char| {
+                match c {
+                    '[' | '{' if depth > 0 => return true,
+                    '[' | '{' => depth += 1,
+                    ']' | '}' if depth > 0 => depth -= 1,
+                    '\n' => return true,
+                    _ => {}
+                }
+                depth == 0 && c.is_whitespace()
+            })
+            .unwrap_or(rest.len())
+        };
+        let start = stripped.len() - rest.len();
+        tokens.push((lines, txt[start..start + end].trim_end()));
+        let next = rest[end..].trim_start();
+        lines += rest[..rest.len() - next.len()].matches('\n').count();
+        rest = next;
+    }
+
+    (tokens, comments, unclosed)
+}
+
+///Parses a score, stopping at the first error
+pub fn parse(txt: &str) -> Result<Score, ScoreError<'_>> {
+    let mut parser = Parser::new(txt);
+    let (tokens, comments, unclosed) = tokens(txt);
+    parser.unclosed_comment = unclosed;
+    for (line, token) in tokens {
+        parser.line = line;
+        let result = parser.token(token);
+        if let Some(e) = parser.errors.drain(..).next() {
+            return Err(e);
         }
+        result?;
+    }
+    match parser.end().into_iter().next() {
+        Some(e) => Err(e),
+        None => Ok(parser.into_score(comments)),
+    }
+}
 
-        panic!("Invalid token: `{}`", token);
+///Parses a score, skipping invalid tokens.
+///Returns the notes that could be parsed, including those of invalid measures,
+///together with every error found
+pub fn parse_lenient(txt: &str) -> (Score, Vec<ScoreError<'_>>) {
+    let mut parser = Parser::new(txt);
+    let (tokens, comments, unclosed) = tokens(txt);
+    parser.unclosed_comment = unclosed;
+    let mut errors = Vec::new();
+    for (line, token) in tokens {
+        parser.line = line;
+        let result = parser.token(token);
+        errors.append(&mut parser.errors);
+        errors.extend(result.err());
     }
+    errors.extend(parser.end());
+    (parser.into_score(comments), errors)
+}
 
-    validate_measure!(out, measure);
-    out.into_iter().flatten().collect()
+#[cfg(test)]
+use crate::articulation::Articulation;
+
+#[cfg(test)]
+fn notes(txt: &str) -> Vec<Note> {
+    parse(txt).unwrap().voices.remove(0).unroll()
 }
 
 #[test]
 fn test_parse() {
-    parse(include_str!("../../a_cruel_angels_thesis.txt"));
+    parse(include_str!("../../a_cruel_angels_thesis.txt")).unwrap();
+
+    let err = parse("C D E F |\n  C#b").unwrap_err();
+    assert_eq!((err.offset, err.line, err.column), (12, 2, 3));
+    assert_eq!((err.token, err.measure), ("C#b", 1));
+    assert!(matches!(
+        err.kind,
+        ScoreErrorKind::Parser(ParserError::AccidentalError(_))
+    ));
+
+    let err = parse("C D E | F G |").unwrap_err();
+    assert_eq!((err.offset, err.token, err.measure), (6, "|", 0));
+    assert!(matches!(err.kind, ScoreErrorKind::InvalidMeasure(..)));
+
+    let err = parse("C D E F X").unwrap_err();
+    assert_eq!((err.column, err.token), (9, "X"));
+    assert!(matches!(err.kind, ScoreErrorKind::InvalidToken));
+}
+
+#[test]
+fn test_parse_lenient() {
+    let (notes, errors) = parse_lenient(include_str!("../../a_cruel_angels_thesis.txt"));
+    assert!(errors.is_empty());
+    assert_eq!(
+        notes,
+        parse(include_str!("../../a_cruel_angels_thesis.txt")).unwrap()
+    );
+
+    let (notes, errors) = parse_lenient("K:Hm C D X E F | G/8 | K:Cbm C/3 C D E F");
+    assert_eq!(notes.voices[0].notes().count(), 9);
+    assert_eq!(
+        errors.iter().map(|e| e.token).collect::<Vec<_>>(),
+        vec!["K:Hm", "X", "|", "K:Cbm", "C/3"]
+    );
+    assert!(matches!(
+        errors[3].kind,
+        ScoreErrorKind::Parser(ParserError::Other("Invalid Key"))
+    ));
+}
+
+#[test]
+fn test_chord() {
+    use crate::note::Pitch::*;
+
+    let chords = notes("K:Dm [C E G]/2 [ A4 C5 ] R/4");
+    assert_eq!(chords.len(), 3);
+    match &chords[0] {
+        Note::Chord(tones, d) => {
+            assert_eq!(
+                tones,
+                &vec![
+                    (C, 4, Accidental::Natural),
+                    (E, 4, Accidental::Natural),
+                    (G, 4, Accidental::Natural)
+                ]
+            );
+            assert_eq!(d.fraction, Fraction::new(1u32, 2u32));
+        }
+        n => panic!("Expected chord, got {:?}", n),
+    }
+    assert!(matches!(&chords[1], Note::Chord(tones, _) if tones[0] == (A, 4, Accidental::Natural)));
+
+    let err = parse("[C E G/4]").unwrap_err();
+    assert_eq!(err.token, "[C E G/4]");
+    assert_eq!(notes("K:F [B D]/2 [B D]/2"), notes("K:F [Bb D]/2 [Bb D]/2"));
+
+    //an unclosed bracket only hides the rest of its line
+    let (_, errors) = parse_lenient("[C E/2 D/2 |\n{D [E G]/2 X |");
+    assert_eq!(
+        errors.iter().map(|e| e.token).collect::<Vec<_>>(),
+        vec!["[C E/2 D/2 |", "{D", "X", "|"]
+    );
+    assert!(matches!(
+        errors[0].kind,
+        ScoreErrorKind::Parser(ParserError::Other("Brackets must be closed"))
+    ));
+}
+
+#[test]
+fn test_voices() {
+    let score = parse(
+        "K:G 3/4 V:melody B/2 C5 | V:bass G3/2. | V:melody D5/2. |\n\
+         V:bass E3/2. | BPM:90 V:melody K:F B/2. |",
+    )
+    .unwrap();
+    assert_eq!(
+        score
+            .voices
+            .iter()
+            .map(|v| v.name.as_str())
+            .collect::<Vec<_>>(),
+        vec!["melody", "bass"]
+    );
+    assert_eq!(score.voices[0].notes().count(), 4);
+    assert_eq!(score.voices[1].notes().count(), 2);
+    assert!(matches!(
+        score.voices[0].notes().nth(3).unwrap(),
+        Note::Note(Pitch::B, 4, Accidental::Flat, _)
+    ));
+
+    let err = parse("V:1 C D E F | V:2 C D E |").unwrap_err();
+    assert_eq!((err.offset, err.measure), (24, 0));
+}
+
+#[test]
+fn test_tuplets() {
+    let triplets = notes("(3 C/8 D/8 E/8 F/4 G/2 | C/8t D/8t E/8t [C E]/4t R/4t [D F]/4t G/4");
+    let durations = triplets
+        .iter()
+        .map(|n| n.duration().fraction)
+        .collect::<Vec<_>>();
+    assert_eq!(
+        durations[..4],
+        [
+            Fraction::new(1u32, 12u32),
+            Fraction::new(1u32, 12u32),
+            Fraction::new(1u32, 12u32),
+            Fraction::new(1u32, 4u32)
+        ]
+    );
+    assert_eq!(durations[8], Fraction::new(1u32, 6u32));
+
+    assert_eq!(notes("(5 C/16 C/16 C/16 C/16 C/16 C/4 C/2").len(), 7);
+    assert_eq!(
+        notes("(7 C/16 C/16 C/16 C/16 C/16 C/16 C/16 C/4 C/2").len(),
+        9
+    );
+    assert_eq!(notes("(4:6 C/8 D/8 E/8 F/8 G/4").len(), 5);
+    assert!(parse("(3 C/8 D/8 E/8 F/8 G/4 A/2").is_err());
+    assert!(matches!(
+        parse("(1 C").unwrap_err().kind,
+        ScoreErrorKind::Parser(ParserError::DurationError(_))
+    ));
+    assert!(matches!(
+        parse("(3:0 C").unwrap_err().kind,
+        ScoreErrorKind::Parser(ParserError::DurationError(
+            "Tuplet must take the time of at least one note"
+        ))
+    ));
+    assert_eq!(
+        parse("C/2 C/4 (3 C/8 D/8 | E/8 F/2..").unwrap_err().token,
+        "(3"
+    );
+    for src in [
+        "(2:4294967295 C/1 C/1 D/1",
+        "(3:2147483647 C/1 C/1 C/1",
+        "(65 C",
+    ] {
+        assert!(matches!(
+            parse(src).unwrap_err().kind,
+            ScoreErrorKind::Parser(ParserError::DurationError(_))
+        ));
+        assert!(!parse_lenient(src).1.is_empty());
+    }
+}
+
+#[test]
+fn test_repeats() {
+    let score = parse("|: C D E F |1 G/1 :|2 C5/1 :|: Segno C/1 | Fine D.S. Coda").unwrap();
+    let elements = &score.voices[0].elements;
+    assert_eq!(elements.len(), 16);
+    assert_eq!(
+        elements[5],
+        Element::Bar(Bar {
+            end_repeat: false,
+            start_repeat: false,
+            volta: vec![1]
+        })
+    );
+    assert_eq!(
+        elements[9],
+        Element::Bar(Bar {
+            end_repeat: true,
+            start_repeat: true,
+            volta: vec![]
+        })
+    );
+    assert_eq!(elements[10], Element::Navigation(Navigation::Segno));
+    assert_eq!(elements[15], Element::Navigation(Navigation::Coda));
+
+    let err = parse("|: C D E :|").unwrap_err();
+    assert_eq!((err.token, err.measure), (":|", 0));
+}
+
+#[test]
+fn test_ties() {
+    let score = parse("C/2 [D F]/2- | [D F]/4- [D F]/4- [D F]/2 | R/1").unwrap();
+    assert_eq!(score.voices[0].elements[2], Element::Tie);
+    let notes = score.voices[0].unroll();
+    assert_eq!(notes.len(), 3);
+    assert_eq!(notes[1].duration().fraction, Fraction::new(3u32, 2u32));
+
+    let err = parse("C/2 D/2- | E/2 D/2").unwrap_err();
+    assert_eq!((err.token, err.measure), ("E/2", 1));
+    assert!(matches!(err.kind, ScoreErrorKind::InvalidTie));
+
+    let src = "C/2 R/2- | R/1";
+    assert_eq!(
+        parse(src).unwrap_err().render(src, "song.txt"),
+        "error: Rests cannot be tied in `R/2-`\n \
+         --> song.txt:1:5\n  \
+         |\n\
+         1 | C/2 R/2- | R/1\n  \
+         |     ^^^^\n"
+    );
+    let src = "C/2 D/2 | E/2 F/2-";
+    let err = parse(src).unwrap_err();
+    assert_eq!((err.token, err.measure), ("F/2-", 1));
+    assert_eq!(
+        err.render(src, "song.txt"),
+        "error: Ties must be followed by a note in `F/2-`\n \
+         --> song.txt:1:15\n  \
+         |\n\
+         1 | C/2 D/2 | E/2 F/2-\n  \
+         |               ^^^^\n"
+    );
+}
+
+#[test]
+fn test_accidentals() {
+    use Accidental::*;
+    let tones = |src| {
+        notes(src)
+            .iter()
+            .flat_map(Note::tones)
+            .map(|(_, _, acc)| acc)
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(
+        tones("F#/4 F/4 F5/4 F/4 | F/1"),
+        vec![Sharp, Sharp, Natural, Sharp, Natural]
+    );
+    assert_eq!(
+        tones("K:G F/4 Fn/4 [F A]/4 F/4 | F/1"),
+        vec![Sharp, Natural, Natural, Natural, Natural, Sharp]
+    );
+    assert_eq!(tones("C/2 Bb/2- | B/2 B/2"), vec![Natural, Flat, Natural]);
+    let err = parse("C/2 Bb/2- | Bn/2 B/2").unwrap_err();
+    assert!(matches!(err.kind, ScoreErrorKind::InvalidTie));
+    assert_eq!(
+        tones("Accidentals:note F#/4 F/4 F/2 | Accidentals:measure F#/4 F/4 F/2"),
+        vec![Sharp, Natural, Natural, Sharp, Sharp, Sharp]
+    );
+}
+
+#[test]
+fn test_relative() {
+    let octaves = |src| {
+        notes(src)
+            .iter()
+            .flat_map(Note::tones)
+            .map(|(p, oct, _)| (p, oct))
+            .collect::<Vec<_>>()
+    };
+    use Pitch::*;
+    assert_eq!(
+        octaves("Octave:relative C E G C | B, C' A/2 | Octave:absolute C/1"),
+        vec![
+            (C, 4),
+            (E, 4),
+            (G, 4),
+            (C, 5),
+            (B, 3),
+            (C, 5),
+            (A, 4),
+            (C, 4)
+        ]
+    );
+    assert_eq!(
+        octaves("Octave:relative [C E G A]/2 B C6/8 D/8"),
+        vec![(C, 4), (E, 4), (G, 4), (A, 4), (B, 3), (C, 6), (D, 6)]
+    );
+    let score = parse("Octave:relative C6/1 | V:b C/1").unwrap();
+    assert_eq!(score.voices[1].notes().next(), notes("C/1").first());
+    let score = parse("Octave:relative C/4' D/4 E F").unwrap();
+    assert_eq!(
+        score.voices[0].elements[1],
+        Element::Articulation(Articulation::Staccato)
+    );
+}
+
+#[test]
+fn test_transposition() {
+    use {Accidental::*, Pitch::*};
+    let tones = |src| notes(src).iter().flat_map(Note::tones).collect::<Vec<_>>();
+    assert_eq!(
+        tones("Transpose:+2 C/4 F#/4 [Bb E5]/2- | [Bb E5]/1"),
+        vec![
+            (D, 4, Natural),
+            (G, 4, Sharp),
+            (C, 5, Natural),
+            (F, 5, Sharp)
+        ]
+    );
+    assert_eq!(
+        tones("K:D Transpose:+1 F/2 C/2 | Transpose:-m3 D/1"),
+        vec![(G, 4, Natural), (D, 4, Natural), (B, 3, Natural)]
+    );
+    assert!(parse("Transpose:X2 C/1").is_err());
+    assert!(parse("Transpose:M4294967295 C/1").is_err());
+    assert!(parse("Transpose:2147483647 C/1").is_err());
+    //notes are left as written when nothing is transposed
+    assert_eq!(
+        tones("K:C# C/4 D/4 E/4 F/4 | K:A#m A/1"),
+        vec![
+            (C, 4, Sharp),
+            (D, 4, Sharp),
+            (E, 4, Sharp),
+            (F, 4, Sharp),
+            (A, 4, Sharp)
+        ]
+    );
+    assert_eq!(tones("K:C# Transpose:+11 C/1"), vec![(C, 5, Natural)]);
+    assert_eq!(tones("Transpose:M2 @a = C/1\n@a^2"), vec![(E, 4, Natural)]);
+    //macros are spelled for the key too
+    assert_eq!(
+        tones("@a = C/4 F/4 [Bb E5]/2\n@a^1 |"),
+        vec![(D, 4, Flat), (G, 4, Flat), (C, 5, Flat), (F, 5, Natural)]
+    );
+}
+
+#[test]
+fn test_comments() {
+    let src = "% Title\nC D /* the\nmiddle */ E F | // end\nG/1 %%";
+    let score = parse(src).unwrap();
+    assert_eq!(score.voices[0].notes().count(), 5);
+    assert_eq!(
+        score.comments,
+        vec![
+            Comment {
+                offset: 0,
+                text: " Title".to_string(),
+                block: false
+            },
+            Comment {
+                offset: 12,
+                text: " the\nmiddle ".to_string(),
+                block: true
+            },
+            Comment {
+                offset: 35,
+                text: " end".to_string(),
+                block: false
+            },
+            Comment {
+                offset: 46,
+                text: "%".to_string(),
+                block: false
+            },
+        ]
+    );
+
+    let src = "C D /* ünïcode */ E X";
+    let err = parse(src).unwrap_err();
+    assert_eq!((err.token, err.offset, err.column), ("X", 22, 21));
+
+    let src = "C D E F |\nG/1 /* never\nended G";
+    let err = parse(src).unwrap_err();
+    assert_eq!((err.token, err.offset, err.line), ("/*", 14, 2));
+    let (score, errors) = parse_lenient(src);
+    assert_eq!(score.voices[0].notes().count(), 5);
+    assert_eq!(errors.len(), 1);
+}
+
+#[test]
+fn test_metadata() {
+    let score = parse(
+        "T: A Cruel Angel's Thesis \n\
+         T:Opening theme\n\
+         C: Hidetoshi Satō % not the lyricist\n\
+         ©: 1995\n\
+         K:Cm C/1",
+    )
+    .unwrap();
+    assert_eq!(
+        score.metadata,
+        ScoreMetadata {
+            title: Some("A Cruel Angel's Thesis\nOpening theme".to_string()),
+            composer: Some("Hidetoshi Satō".to_string()),
+            arranger: None,
+            copyright: Some("1995".to_string()),
+        }
+    );
+    assert_eq!(score.voices[0].notes().count(), 1);
+
+    let score = parse("T: Title /* hidden */ more\nC/1").unwrap();
+    assert_eq!(score.metadata.title.as_deref(), Some("Title more"));
+}
+
+#[test]
+fn test_pickup() {
+    assert_eq!(notes("3/4 Pickup C/4 | E/2. | G/2 ").len(), 3);
+    assert_eq!(notes("K:G Pickup D/8 E/8 | F/1 | G/2 R/4 ").len(), 5);
+    assert_eq!(notes("Pickup C/1 | D/1").len(), 2);
+
+    let err = parse("Pickup C/4 | D/1 | E/4").unwrap_err();
+    assert_eq!((err.measure, err.offset), (2, 22));
+    let err = parse("C/4 | D/1").unwrap_err();
+    assert_eq!((err.token, err.measure), ("|", 0));
+    assert!(parse("Pickup C/4 | D/2 | E/2 F/2").is_err());
+    assert_eq!(notes("Pickup C/4 | D/1 | E/2. |").len(), 3);
+    let err = parse("Pickup C/4 | D/2. | E/1 |").unwrap_err();
+    assert_eq!((err.token, err.measure), ("|", 1));
+    assert!(parse("C/1 | Pickup D/1").is_err());
+}
+
+#[test]
+fn test_signatures() {
+    let score = parse("6/8 C/4. D/4. | 7/8(2+2+3) C/4 D/4 E/4. | (3+4) C/4. D/2 |").unwrap();
+    let signatures = score.voices[0]
+        .elements
+        .iter()
+        .filter_map(|e| match e {
+            Element::Signature(s) => Some(s.to_string()),
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(signatures, vec!["6/8", "7/8 (2+2+3)", "7/8 (3+4)"]);
+
+    let err = parse("6/8 C/4 D/4 E/4 F/4 |").unwrap_err();
+    assert_eq!(err.to_string(), "Invalid measure no. 0: 1 ≠ 6/8 at 1:21");
+    assert!(matches!(
+        parse("7/8(2+2)").unwrap_err().kind,
+        ScoreErrorKind::Parser(ParserError::Other(_))
+    ));
+    assert!(parse("4/4 (2+3)").is_err());
+    assert!(parse("4/4 (4294967295+1) C/1").is_err());
+    assert!(parse("1000000000/4 C/1").is_err());
+    for src in ["4/0 C/1", "6/0 C/4.", "4/3 C/1"] {
+        assert!(matches!(
+            parse(src).unwrap_err().kind,
+            ScoreErrorKind::Parser(ParserError::Other(_))
+        ));
+    }
+
+    //BPM counts dotted quarters in compound meters
+    let score = parse("BPM:60 6/8 C/4. C/4. | 3/4 C/4 C/2").unwrap();
+    let events = score.voices[0].events(&score.tempo_map());
+    assert_eq!(events[0].length, std::time::Duration::from_secs(1));
+    assert_eq!(events[2].length, std::time::Duration::from_secs(1));
+}
+
+#[test]
+fn test_render() {
+    let src = "4/4 C D E F |\n7/8 C D E F |\n";
+    assert_eq!(
+        parse(src).unwrap_err().render(src, "song.txt"),
+        "error: Invalid measure no. 1: 1 ≠ 7/8\n \
+         --> song.txt:2:13\n  \
+         |\n\
+         2 | 7/8 C D E F |\n  \
+         |             ^ measure ends here\n"
+    );
+
+    let src = "C D E F\nK:Hb";
+    assert_eq!(
+        parse(src).unwrap_err().render(src, "song.txt"),
+        "error: Unexpected `Hb` in `K:Hb`\n \
+         --> song.txt:2:3\n  \
+         |\n\
+         2 | K:Hb\n  \
+         |   ^^\n"
+    );
+}
+
+#[test]
+fn test_lyrics() {
+    let score = parse(
+        "C/4 D/4 E/4- E/4 | R/4 F/4 G/2 |\n\
+         W: Zan-ko-ku _ na\n\
+         2/4 C/4 D/4 | E/2 |\n\
+         W: ten- shi\n\
+         E/4 D/4 | C/2 |",
+    )
+    .unwrap();
+    let lyrics = score.voices[0]
+        .events(&score.tempo_map())
+        .iter()
+        .map(|e| e.lyric.as_ref().map_or("", |s| s.text.as_str()).to_string())
+        .collect::<Vec<_>>();
+    assert_eq!(
+        lyrics,
+        vec!["Zan", "ko", "ku", "", "", "na", "ten", "shi", "", "", "", ""]
+    );
+
+    //verses are sung in turn as the notes repeat
+    let score = parse("|: C/2 D/2 :|\nW: one two\nW: three four\nD.C.").unwrap();
+    let lyrics = score.voices[0]
+        .events(&score.tempo_map())
+        .iter()
+        .map(|e| e.lyric.as_ref().map_or("", |s| s.text.as_str()).to_string())
+        .collect::<Vec<_>>();
+    assert_eq!(lyrics, vec!["one", "two", "three", "four", "three", "four"]);
+    //notes from a macro are on the line of its reference
+    let score = parse("@a = C/2 D/2\nE/1 |\n@a |\nW: x y").unwrap();
+    assert_eq!(
+        score.voices[0].events(&score.tempo_map())[1]
+            .lyric
+            .as_ref()
+            .unwrap()
+            .text,
+        "x"
+    );
+
+    let err = parse("C/2 D/2\nW: a b c").unwrap_err();
+    assert_eq!((err.line, err.token), (2, "W: a b c"));
+    let score = parse("C/2 D/2\nW: la /* x */ lo").unwrap();
+    assert_eq!(score.voices[0].elements.len(), 4);
+}
+
+#[test]
+fn test_macros() {
+    let score = parse(
+        "@riff = C/8 D/8 E/8 F/8 % four eighths\n\
+         @bar = @riff G/2 |\n\
+         @bar @bar^7 @riff^-12 @riff^2 |",
+    )
+    .unwrap();
+    let notes = score.voices[0].unroll();
+    assert_eq!(notes.len(), 18);
+    assert_eq!(
+        notes[5],
+        Note::Note(Pitch::G, 4, Accidental::Natural, Duration::new(8, 0))
+    );
+    assert_eq!(
+        notes[10],
+        Note::Note(Pitch::C, 3, Accidental::Natural, Duration::new(8, 0))
+    );
+    assert_eq!(
+        notes[16],
+        Note::Note(Pitch::F, 4, Accidental::Sharp, Duration::new(8, 0))
+    );
+
+    let err = parse("@a = C/4 @b\n@b = @a |\n@a").unwrap_err();
+    assert_eq!((err.token, err.line), ("@a", 2));
+    let err = parse("C/1 | @missing").unwrap_err();
+    assert_eq!(err.token, "@missing");
+    //errors point into the definition
+    let err = parse("@bar = C D E |\n@bar").unwrap_err();
+    assert_eq!((err.token, err.line), ("|", 1));
+
+    //each macro using the last twice would expand to 2^40 notes
+    let mut src = "@m0 = C/1 |\n".to_string();
+    for i in 1..=40 {
+        src += &format!("@m{} = @m{1} @m{1}\n", i, i - 1);
+    }
+    src += "@m40";
+    let err = parse(&src).unwrap_err();
+    assert!(err.token.starts_with("@m"));
+    assert_eq!(parse_lenient(&src).1.len(), 1);
+
+    let (_, errors) = parse_lenient("@a = X C/1 Y |\n@a");
+    assert_eq!(
+        errors.iter().map(|e| e.token).collect::<Vec<_>>(),
+        vec!["X", "Y"]
+    );
+}
+
+#[test]
+fn test_modes() {
+    assert!(matches!(
+        notes("K:GDor B/2 K:EbLyd A/2")[..],
+        [
+            Note::Note(Pitch::B, 4, Accidental::Flat, _),
+            Note::Note(Pitch::A, 4, Accidental::Natural, _)
+        ]
+    ));
+    assert!(parse("K:DDorian").is_err());
+}
+
+#[test]
+fn test_custom_keys() {
+    assert_eq!(
+        notes("K:[F# Bb Eb] E/4 F/4 B/4 [C E]/4 | K:none E/4 F/4 B/2"),
+        notes("Eb/4 F#/4 Bb/4 [C Eb]/4 | E/4 F/4 B/2")
+    );
+    assert!(parse("K:[F# Fn]").is_err());
+    assert!(parse("K:[F# X]").is_err());
+}
+
+#[test]
+fn test_durations() {
+    let shortest = |src: &str| {
+        let (score, _) = parse_lenient(src);
+        let note = score.voices[0].notes().next().unwrap().clone();
+        note.duration().fraction.denom().copied()
+    };
+    assert_eq!(shortest("C/2147483648"), Some(1 << 31));
+    assert_eq!(shortest(&format!("C/1{}", ".".repeat(31))), Some(1 << 31));
+    for src in [
+        format!("C/1{}", ".".repeat(33)),
+        "C/2147483648.".to_string(),
+    ] {
+        assert!(matches!(
+            parse(&src).unwrap_err().kind,
+            ScoreErrorKind::Parser(ParserError::DurationError("Duration has too many dots"))
+        ));
+        assert!(!parse_lenient(&src).1.is_empty());
+    }
 }
@@ -0,0 +1,596 @@
+use {
+    crate::{
+        articulation::Articulation,
+        duration::{Duration, Fraction},
+        dynamic::{Dynamic, Hairpin},
+        lyrics::Syllable,
+        note::Note,
+        signature::TimeSignature,
+        tempo::{Gradual, Tempo, TempoMap},
+    },
+    fraction::ToPrimitive,
+    std::{mem, time},
+};
+
+///A bar line, possibly marking the start or end of a repeat
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Bar {
+    pub end_repeat: bool,
+    pub start_repeat: bool,
+    ///the passes through the repeat on which the following ending is played, empty if not an ending
+    pub volta: Vec<u32>,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Navigation {
+    Segno,
+    ///marks both where to jump from and where the coda starts
+    Coda,
+    Fine,
+    DaCapo,
+    DalSegno,
+}
+
+///An element of a voice as written in the score
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Element {
+    Note(Note),
+    ///ties the previous note to the next one
+    Tie,
+    Bar(Bar),
+    Navigation(Navigation),
+    Signature(TimeSignature),
+    ///sets the tempo of every voice from this point on
+    Tempo(Tempo),
+    ///moves the tempo steadily towards the next one
+    Gradual(Gradual),
+    ///holds the previous note for the given factor of its length
+    Fermata(Fraction),
+    Dynamic(Dynamic),
+    ///moves the dynamic steadily towards the next one
+    Hairpin(Hairpin),
+    ///articulates the previous note
+    Articulation(Articulation),
+    ///the following notes are played legato up to `SlurEnd`
+    SlurStart,
+    SlurEnd,
+    ///the notes played in place of the next note, such as grace notes or a trill
+    Ornament(Vec<Note>),
+    ///slides from the previous note to the next
+    Glissando,
+    ///the syllable sung to the previous note
+    Lyric(Syllable),
+}
+
+///A line of music, played at the same time as the other voices of a score
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Voice {
+    pub name: String,
+    pub elements: Vec<Element>,
+}
+
+impl Voice {
+    ///the notes as written, ignoring repeats and navigation
+    pub fn notes(&self) -> impl Iterator<Item = &Note> {
+        self.elements.iter().filter_map(|e| match e {
+            Element::Note(n) => Some(n),
+            _ => None,
+        })
+    }
+
+    ///the number of passes through the repeat starting at `start`, given by its last ending
+    fn passes(&self, start: usize) -> u32 {
+        self.elements[start..]
+            .iter()
+            .filter_map(|e| match e {
+                Element::Bar(bar) => Some(bar),
+                _ => None,
+            })
+            .take_while(|bar| !bar.start_repeat)
+            .flat_map(|bar| bar.volta.iter().copied())
+            .fold(2, u32::max)
+    }
+
+    ///Unrolls repeats, endings and D.C./D.S. jumps into the notes and tempo changes as performed.
+    ///Tied notes are joined into one.
+    ///
+    ///Repeats are not taken again after a jump, and only the last ending is played.
+    ///After a jump the music ends at `Fine`, or skips from one `Coda` to the next.
+    ///`D.S.` without a `Segno` jumps to the start
+    fn performance(&self) -> Vec<Element> {
+        let mut out = Vec::<Element>::new();
+        let mut i = 0;
+        //the element a repeat jumps back to
+        let mut start = 0;
+        let mut pass = 1;
+        let mut jumped = false;
+        //whether in an ending not played on this pass
+        let mut skipping = false;
+        let mut tied = false;
+
+        //the times each element has been played
+        let mut plays = vec![0; self.elements.len()];
+
+        while let Some(element) = self.elements.get(i) {
+            plays[i] += 1;
+            i += 1;
+            match element {
+                Element::Note(note) if !skipping => {
+                    let last = out.iter_mut().rev().find_map(|e| match e {
+                        Element::Note(n) => Some(n),
+                        _ => None,
+                    });
+                    match last {
+                        Some(last) if mem::take(&mut tied) => {
+                            *last.duration_mut() = [last.duration(), note.duration()].iter().sum();
+                        }
+                        _ => out.push(element.clone()),
+                    }
+                }
+                Element::Note(_) => {}
+                Element::Tie => tied = !skipping,
+                Element::Bar(bar) => {
+                    if bar.end_repeat {
+                        if !skipping && !jumped {
+                            if pass < self.passes(start) {
+                                pass += 1;
+                                i = start;
+                                continue;
+                            }
+                            //a following end repeat without a start repeats from here
+                            start = i;
+                            pass = 1;
+                        }
+                        skipping = false;
+                    }
+                    if bar.start_repeat {
+                        start = i;
+                        pass = 1;
+                    }
+                    if !bar.volta.is_empty() {
+                        let pass = if jumped { self.passes(start) } else { pass };
+                        skipping = !bar.volta.contains(&pass);
+                    }
+                }
+                Element::Navigation(nav) if !skipping => match nav {
+                    Navigation::DaCapo | Navigation::DalSegno if !jumped => {
+                        jumped = true;
+                        i = match nav {
+                            Navigation::DalSegno => self
+                                .elements
+                                .iter()
+                                .position(|e| *e == Element::Navigation(Navigation::Segno))
+                                .map_or(0, |s| s + 1),
+                            _ => 0,
+                        };
+                        start = i;
+                    }
+                    Navigation::Fine if jumped => break,
+                    Navigation::Coda if jumped => {
+                        if let Some(next) = self.elements[i..]
+                            .iter()
+                            .position(|e| *e == Element::Navigation(Navigation::Coda))
+                        {
+                            i += next + 1;
+                        }
+                    }
+                    _ => {}
+                },
+                //verses are sung in turn each time the note is played, the last from then on
+                Element::Lyric(syllable) if !skipping => {
+                    if syllable.verse == (plays[i - 1] - 1).min(self.last_verse(i - 1)) {
+                        out.push(element.clone())
+                    }
+                }
+                Element::Signature(_)
+                | Element::Tempo(_)
+                | Element::Gradual(_)
+                | Element::Fermata(_)
+                | Element::Dynamic(_)
+                | Element::Hairpin(_)
+                | Element::Articulation(_)
+                | Element::SlurStart
+                | Element::SlurEnd
+                | Element::Ornament(_)
+                | Element::Glissando
+                    if !skipping =>
+                {
+                    out.push(element.clone())
+                }
+                Element::Navigation(_)
+                | Element::Signature(_)
+                | Element::Tempo(_)
+                | Element::Gradual(_)
+                | Element::Fermata(_)
+                | Element::Dynamic(_)
+                | Element::Hairpin(_)
+                | Element::Articulation(_)
+                | Element::SlurStart
+                | Element::SlurEnd
+                | Element::Ornament(_)
+                | Element::Glissando
+                | Element::Lyric(_) => {}
+            }
+        }
+
+        out
+    }
+
+    ///the last verse of the syllables given to a note, `at` being one of them
+    fn last_verse(&self, at: usize) -> u32 {
+        let start = self.elements[..at]
+            .iter()
+            .rposition(|e| !matches!(e, Element::Lyric(_)))
+            .map_or(0, |i| i + 1);
+        self.elements[start..]
+            .iter()
+            .map_while(|e| match e {
+                Element::Lyric(syllable) => Some(syllable.verse),
+                _ => None,
+            })
+            .max()
+            .unwrap_or(0)
+    }
+
+    ///the notes as performed, see `performance`
+    pub fn unroll(&self) -> Vec<Note> {
+        self.performance()
+            .into_iter()
+            .filter_map(|e| match e {
+                Element::Note(n) => Some(n),
+                _ => None,
+            })
+            .collect()
+    }
+
+    ///adds the tempo changes and fermatas as performed to `map`
+    fn add_tempos(&self, map: &mut TempoMap) {
+        let mut at = Fraction::new(0u32, 1u32);
+        let mut last = at;
+        for e in self.performance() {
+            match e {
+                Element::Note(n) => {
+                    last = at;
+                    at += n.duration().fraction;
+                }
+                Element::Tempo(t) => map.insert(at, t),
+                Element::Gradual(_) => map.ramp(at),
+                Element::Fermata(factor) => map.hold(last, at - last, factor),
+                _ => {}
+            }
+        }
+    }
+
+    ///adds the beat of each signature as performed to `map`
+    fn add_beats(&self, map: &mut TempoMap) {
+        let mut at = Fraction::new(0u32, 1u32);
+        for e in self.performance() {
+            match e {
+                Element::Note(n) => at += n.duration().fraction,
+                Element::Signature(s) => map.set_beat(at, s.beat()),
+                _ => {}
+            }
+        }
+    }
+
+    ///The notes as performed with the time they last, how loud they are and how they are articulated.
+    ///A hairpin moves the velocity steadily towards the next dynamic.
+    ///Slurred notes sound for their full length and all but the first are attacked softly,
+    ///as are the notes of an ornament. A glissando slides to the first pitch of the next note
+    pub fn events(&self, tempo: &TempoMap) -> Vec<Event> {
+        let performance = self.performance();
+        let mut at = Fraction::new(0u32, 1u32);
+        let mut velocity = Dynamic::default().velocity();
+        //the start and end of the current hairpin and the velocities at each
+        let mut hairpin = None;
+        //whether in a slur and whether its first note has been played
+        let mut slur = None;
+        let mut ornament = None;
+        //the event sliding to the next note
+        let mut glissando = None;
+        //index of the first event of the last note
+        let mut first = 0;
+        let mut events = Vec::new();
+
+        for (i, e) in performance.iter().enumerate() {
+            match e {
+                Element::Note(note) => {
+                    let mut pieces = ornament.take().unwrap_or_else(|| vec![note.clone()]);
+                    //the last piece of an ornamented note holds any tied notes
+                    let played = pieces.iter().map(Note::duration).sum::<Duration>();
+                    if let Some(last) = pieces.last_mut() {
+                        let held = last.duration().fraction + note.duration().fraction;
+                        *last.duration_mut() = Duration::from(held - played.fraction);
+                    }
+
+                    first = events.len();
+                    if let Some(from) = glissando.take() {
+                        let event: &mut Event = &mut events[from];
+                        event.glide = pieces.first().map(Note::frequencies);
+                    }
+                    let last = pieces.len() - 1;
+                    for (i, piece) in pieces.into_iter().enumerate() {
+                        let length = piece.duration().fraction;
+                        let velocity = match hairpin {
+                            Some((start, end, from, to)) => {
+                                let progress = ((at - start) / (end - start)).to_f32().unwrap();
+                                from + (to - from) * progress
+                            }
+                            None => velocity,
+                        };
+                        let time = tempo.duration(at, length);
+                        let (sounding, attack) = match slur {
+                            Some(false) => (time, 1.),
+                            Some(true) => (time, Articulation::SLURRED),
+                            None => (time.mul_f32(Articulation::NON_LEGATO), 1.),
+                        };
+                        slur = slur.map(|_| true);
+                        events.push(Event {
+                            note: piece,
+                            length: time,
+                            sounding: if i < last { time } else { sounding },
+                            velocity,
+                            attack: if i > 0 { Articulation::SLURRED } else { attack },
+                            glide: None,
+                            lyric: None,
+                        });
+                        at += length;
+                    }
+                }
+                Element::Ornament(notes) => ornament = Some(notes.clone()),
+                Element::Glissando => glissando = events.len().checked_sub(1),
+                Element::Lyric(syllable) => {
+                    if let Some(event) = events.get_mut(first) {
+                        event.lyric = Some(syllable.clone());
+                    }
+                }
+                Element::Articulation(articulation) => {
+                    if let Some(event) = events.last_mut() {
+                        match articulation {
+                            Articulation::Staccato => event.sounding = event.length / 2,
+                            Articulation::Accent => events[first].attack = Articulation::ACCENT,
+                            Articulation::Tenuto => event.sounding = event.length,
+                        }
+                    }
+                }
+                Element::SlurStart => slur = Some(false),
+                Element::SlurEnd => slur = None,
+                Element::Dynamic(d) => {
+                    velocity = d.velocity();
+                    hairpin = None;
+                }
+                Element::Hairpin(_) => {
+                    let mut end = at;
+                    hairpin = performance[i..].iter().find_map(|e| match e {
+                        Element::Note(n) => {
+                            end += n.duration().fraction;
+                            None
+                        }
+                        Element::Dynamic(d) => Some((at, end, velocity, d.velocity())),
+                        _ => None,
+                    });
+                }
+                _ => {}
+            }
+        }
+
+        events
+    }
+}
+
+///A note as performed
+#[derive(Debug, Clone, PartialEq)]
+pub struct Event {
+    pub note: Note,
+    ///the time until the next note
+    pub length: time::Duration,
+    ///the time the note is heard, at most `length`
+    pub sounding: time::Duration,
+    ///the factor the note's envelope is scaled by
+    pub velocity: f32,
+    ///the strength of the start of the note relative to a normal one
+    pub attack: f32,
+    ///the frequencies the note slides to over its sounding length
+    pub glide: Option<Vec<f32>>,
+    pub lyric: Option<Syllable>,
+}
+
+///A comment stripped from the score
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Comment {
+    ///byte offset of the comment in the source, including its delimiter
+    pub offset: usize,
+    ///the text between the delimiters
+    pub text: String,
+    ///whether a `/* */` comment rather than a line comment
+    pub block: bool,
+}
+
+///Header fields of a score, repeated fields are joined by newlines
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ScoreMetadata {
+    pub title: Option<String>,
+    pub composer: Option<String>,
+    pub arranger: Option<String>,
+    pub copyright: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Score {
+    pub metadata: ScoreMetadata,
+    pub voices: Vec<Voice>,
+    pub comments: Vec<Comment>,
+}
+
+impl Score {
+    ///the tempo changes and fermatas of all voices, a later voice overriding earlier ones at the same position.
+    ///A signature only changes the beat, keeping the bpm any voice set
+    pub fn tempo_map(&self) -> TempoMap {
+        let mut map = TempoMap::default();
+        for voice in &self.voices {
+            voice.add_tempos(&mut map);
+        }
+        for voice in &self.voices {
+            voice.add_beats(&mut map);
+        }
+        map
+    }
+}
+
+#[cfg(test)]
+use crate::parse::parse;
+
+#[test]
+fn test_unroll() {
+    //every pitch is a whole note
+    let unroll = |txt: &str| {
+        let txt = txt
+            .split_whitespace()
+            .map(|t| match t {
+                "A" | "B" | "C" | "D" | "E" | "F" | "G" => format!("{}/1", t),
+                _ => t.to_string(),
+            })
+            .collect::<Vec<_>>()
+            .join(" ");
+        parse(&txt).unwrap().voices[0]
+            .unroll()
+            .iter()
+            .map(|n| match n {
+                Note::Note(p, ..) => format!("{:?}", p),
+                _ => unreachable!(),
+            })
+            .collect::<String>()
+    };
+
+    assert_eq!(unroll("A |: B :| C"), "ABBC");
+    assert_eq!(unroll("A :| B |: C :|: D :|"), "AABCCDD");
+    assert_eq!(unroll("A :| B :|"), "AABB");
+    assert_eq!(unroll("|: A |1 B :|2 C | D"), "ABACD");
+    assert_eq!(unroll("|: A |1 B :|2 C :|3 D | E"), "ABACADE");
+    assert_eq!(unroll("|: A |1,2 B :|3 C |"), "ABABAC");
+    assert_eq!(unroll("A | Segno B Fine | C D.S. | D"), "ABCB");
+    assert_eq!(unroll("A |: B :| C D.C."), "ABBCABC");
+    assert_eq!(unroll("|: A |1 B :|2 C | D.C."), "ABACAC");
+    assert_eq!(unroll("A | Coda B | D.C. Coda C | D"), "ABACD");
+    assert_eq!(unroll("A | E | F | G D.S."), "AEFGAEFG");
+    assert_eq!(unroll("A/1- | A | B"), "AB");
+}
+
+#[test]
+fn test_tempo() {
+    let score = parse("V:1 BPM:60 C/1 | BPM:120 C/1 | V:2 C/2 C/2 | C/1").unwrap();
+    let seconds = |score: &Score, voice: usize| {
+        score.voices[voice]
+            .events(&score.tempo_map())
+            .iter()
+            .map(|e| e.length.as_secs_f32())
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(seconds(&score, 0), vec![4., 2.]);
+    assert_eq!(seconds(&score, 1), vec![2., 2., 2.]);
+
+    //a tempo change inside a repeat is performed on every pass
+    let score = parse("|: BPM:60 C/1 :| BPM:120 C/1").unwrap();
+    assert_eq!(seconds(&score, 0), vec![4., 4., 2.]);
+
+    //a new signature keeps the bpm set by another voice
+    let score = parse("V:1 C/1 | V:2 C/1 | V:1 6/8 BPM:30 C/4. C/4. | V:2 6/8 C/4. C/4. |");
+    let score = score.unwrap();
+    assert_eq!(seconds(&score, 0), vec![2., 2., 2.]);
+    assert_eq!(seconds(&score, 1), vec![2., 2., 2.]);
+
+    let err = parse("BPM:0 C/1").unwrap_err();
+    assert_eq!(err.token, "BPM:0");
+}
+
+#[test]
+fn test_gradual() {
+    let score = parse("BPM:60 C/1 | rit. C/1 | BPM:30 C/2^ C/2^3/2 | R/1^").unwrap();
+    let events = score.voices[0].events(&score.tempo_map());
+    let seconds = events
+        .iter()
+        .map(|e| e.length.as_secs_f32())
+        .collect::<Vec<_>>();
+    assert_eq!(seconds[0], 4.);
+    assert!(seconds[1] > 4. && seconds[1] < 8.);
+    assert_eq!(seconds[2..], [8., 6., 16.]);
+
+    assert!(parse("BPM:60 accel. C/1 | BPM:40 C/1").is_err());
+    let err = parse("rit. C/1 | C/1").unwrap_err();
+    assert_eq!(err.token, "rit.");
+}
+
+#[test]
+fn test_dynamics() {
+    let score = parse("C/2 p C/2 | < C/4 C/4 C/4 C/4 | f C/2 > C/2 | pp C/1").unwrap();
+    let velocities = score.voices[0]
+        .events(&score.tempo_map())
+        .iter()
+        .map(|e| e.velocity)
+        .collect::<Vec<_>>();
+    assert_eq!(velocities[..2], [0.7, 0.4]);
+    assert!(velocities[2..6]
+        .windows(2)
+        .all(|w| w[0] < w[1] && w[1] < 0.85));
+    assert_eq!(velocities[6..], [0.85, 0.85, 0.25]);
+
+    assert!(parse("f C/1 | < C/1 | p C/1").is_err());
+    assert_eq!(parse("C/1 > C/1").unwrap_err().token, ">");
+}
+
+#[test]
+fn test_articulations() {
+    let score = parse("BPM:60 C/4' D/4> E/4_ F/4 | ( C/4 D/4 E/4 ) F/4'> |").unwrap();
+    let events = score.voices[0].events(&score.tempo_map());
+    let sounding = events
+        .iter()
+        .map(|e| e.sounding.as_secs_f32())
+        .collect::<Vec<_>>();
+    assert_eq!(sounding, vec![0.5, 0.9, 1., 0.9, 1., 1., 1., 0.5]);
+    let attacks = events.iter().map(|e| e.attack).collect::<Vec<_>>();
+    assert_eq!(attacks, vec![1., 1.5, 1., 1., 1., 0.5, 0.5, 1.5]);
+
+    assert!(parse("( C/1 | ( C/1 ) |").is_err());
+    assert_eq!(parse("C/1 ) |").unwrap_err().token, ")");
+    assert_eq!(parse("( C/1 |").unwrap_err().token, "(");
+
+    //slurs can be attached to their first and last notes
+    let attached = parse("BPM:60 C/4' D/4> E/4_ F/4 | (C/4 D/4 E/4) F/4'> |").unwrap();
+    assert_eq!(attached.voices[0].events(&attached.tempo_map()), events);
+    assert_eq!(parse("C/1 D/1) |").unwrap_err().token, "D/1)");
+}
+
+#[test]
+fn test_ornamented() {
+    let score = parse("2/4 K:D {E}D/4> tr C/4 | mord B/2- | B/4 turn A/4 |").unwrap();
+    assert_eq!(score.voices[0].unroll().len(), 4);
+    let events = score.voices[0].events(&score.tempo_map());
+    let pitches = events
+        .iter()
+        .map(|e| match e.note {
+            Note::Note(p, _, acc, _) => format!("{:?}{}", p, i32::from(acc)),
+            _ => unreachable!(),
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(pitches[..4], ["E0", "D0", "C1", "D0"]);
+    assert_eq!(pitches[10..], ["B0", "A0", "B0", "B0", "A0", "G0", "A0"]);
+    assert_eq!(events[0].attack, Articulation::ACCENT);
+    //the mordent's last note holds the tied note
+    assert_eq!(events[12].length, time::Duration::from_secs_f32(1.375));
+
+    assert!(parse("tr [C E]/1").is_err());
+    assert_eq!(parse("C/1 | turn").unwrap_err().token, "turn");
+}
+
+#[test]
+fn test_glissando() {
+    let score = parse("C/4 ~> G/4 [C E]/4 ~> [D F]/4").unwrap();
+    let events = score.voices[0].events(&score.tempo_map());
+    assert_eq!(events[0].glide, Some(vec![392.]));
+    assert_eq!(events[1].glide, None);
+    assert_eq!(events[2].glide, Some(vec![293.66, 349.23]));
+
+    assert_eq!(parse("~> C/1").unwrap_err().token, "~>");
+    assert_eq!(parse("C/2 ~> R/2").unwrap_err().token, "R/2");
+    assert_eq!(parse("C/1 ~>").unwrap_err().token, "~>");
+}
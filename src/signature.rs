@@ -0,0 +1,118 @@
+use {crate::duration::Fraction, std::fmt};
+
+///A time signature with its notes grouped into beats
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TimeSignature {
+    pub numerator: u32,
+    pub denominator: u32,
+    ///the number of notes of the denominator in each beat
+    pub groups: Vec<u32>,
+}
+
+///the most notes a measure can be signed for
+pub const MAX_NUMERATOR: u32 = 64;
+
+impl TimeSignature {
+    ///Compound meters such as 6/8 are grouped in threes, others in single notes
+    #[allow(clippy::manual_is_multiple_of)] //is_multiple_of needs Rust 1.87
+    pub fn new(numerator: u32, denominator: u32) -> Self {
+        let groups = if numerator > 3 && numerator % 3 == 0 {
+            vec![3; (numerator / 3) as usize]
+        } else {
+            vec![1; numerator as usize]
+        };
+        Self {
+            numerator,
+            denominator,
+            groups,
+        }
+    }
+
+    pub fn with_groups(self, groups: Vec<u32>) -> Result<Self, &'static str> {
+        let sum = groups.iter().try_fold(0u32, |sum, &g| sum.checked_add(g));
+        if groups.contains(&0) || sum != Some(self.numerator) {
+            Err("Beat groups must add up to the signature")
+        } else {
+            Ok(Self { groups, ..self })
+        }
+    }
+
+    ///the length of a measure
+    pub fn length(&self) -> Fraction {
+        Fraction::new(self.numerator, self.denominator)
+    }
+
+    pub fn is_compound(&self) -> bool {
+        self.groups.len() > 1 && self.groups.iter().all(|&g| g == 3)
+    }
+
+    ///the note counted by the BPM, a dotted note in compound meters and a quarter otherwise
+    pub fn beat(&self) -> Fraction {
+        if self.is_compound() {
+            Fraction::new(3u32, self.denominator)
+        } else {
+            Fraction::new(1u32, 4u32)
+        }
+    }
+
+    ///the position of each beat in a measure, the first being the downbeat
+    pub fn beats(&self) -> Vec<Fraction> {
+        self.groups
+            .iter()
+            .scan(0, |start, &g| {
+                *start += g;
+                Some(Fraction::new(*start - g, self.denominator))
+            })
+            .collect()
+    }
+}
+
+impl Default for TimeSignature {
+    fn default() -> Self {
+        Self::new(4, 4)
+    }
+}
+
+impl fmt::Display for TimeSignature {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.numerator, self.denominator)?;
+        if self.groups.iter().any(|&g| g != 1) && !self.is_compound() {
+            let groups = self.groups.iter().map(u32::to_string).collect::<Vec<_>>();
+            write!(f, " ({})", groups.join("+"))?;
+        }
+        Ok(())
+    }
+}
+
+#[test]
+fn test_signature() {
+    let common = TimeSignature::default();
+    assert_eq!(common.length(), Fraction::new(1u32, 1u32));
+    assert_eq!(common.beat(), Fraction::new(1u32, 4u32));
+    assert_eq!(common.beats().len(), 4);
+    assert_eq!(common.to_string(), "4/4");
+
+    let six_eight = TimeSignature::new(6, 8);
+    assert_ne!(six_eight, TimeSignature::new(3, 4));
+    assert_eq!(six_eight.length(), TimeSignature::new(3, 4).length());
+    assert!(six_eight.is_compound());
+    assert_eq!(six_eight.beat(), Fraction::new(3u32, 8u32));
+    assert_eq!(
+        six_eight.beats(),
+        vec![Fraction::new(0u32, 1u32), Fraction::new(3u32, 8u32)]
+    );
+
+    let seven_eight = TimeSignature::new(7, 8).with_groups(vec![2, 2, 3]).unwrap();
+    assert!(!seven_eight.is_compound());
+    assert_eq!(seven_eight.beat(), Fraction::new(1u32, 4u32));
+    assert_eq!(
+        seven_eight.beats(),
+        vec![
+            Fraction::new(0u32, 1u32),
+            Fraction::new(1u32, 4u32),
+            Fraction::new(1u32, 2u32)
+        ]
+    );
+    assert_eq!(seven_eight.to_string(), "7/8 (2+2+3)");
+    assert!(TimeSignature::new(7, 8).with_groups(vec![2, 2]).is_err());
+}
@@ -0,0 +1,236 @@
+use {crate::duration::Fraction, fraction::ToPrimitive, std::time};
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Tempo {
+    pub bpm: u32,
+    ///the note counted as one beat
+    pub beat: Fraction,
+}
+
+impl Tempo {
+    pub fn new(bpm: u32, beat: Fraction) -> Self {
+        Self { bpm, beat }
+    }
+
+    ///the length of a whole note in seconds
+    fn whole_note(&self) -> f64 {
+        60. / (self.bpm as f64 * self.beat.to_f64().unwrap())
+    }
+}
+
+///120 quarter notes per minute
+impl Default for Tempo {
+    fn default() -> Self {
+        Self::new(120, Fraction::new(1u32, 4u32))
+    }
+}
+
+///A change of tempo spread over the notes up to the next tempo
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Gradual {
+    Accelerando,
+    Ritardando,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Change {
+    at: Fraction,
+    tempo: Tempo,
+    ///whether the tempo moves steadily towards the next change
+    gradual: bool,
+}
+
+///The tempo over the course of a piece, converting positions in whole notes to time
+#[derive(Debug, Clone, PartialEq)]
+pub struct TempoMap {
+    ///tempo changes ordered by position
+    changes: Vec<Change>,
+    ///the start, length and factor of notes held longer than written
+    holds: Vec<(Fraction, Fraction, Fraction)>,
+}
+
+impl TempoMap {
+    fn find(&self, at: Fraction) -> Result<usize, usize> {
+        self.changes
+            .binary_search_by(|c| c.at.partial_cmp(&at).unwrap())
+    }
+
+    ///Sets the tempo from `at` onwards, replacing any change at the same position
+    pub fn insert(&mut self, at: Fraction, tempo: Tempo) {
+        match self.find(at) {
+            Ok(i) => self.changes[i].tempo = tempo,
+            Err(i) => self.changes.insert(
+                i,
+                Change {
+                    at,
+                    tempo,
+                    gradual: false,
+                },
+            ),
+        }
+    }
+
+    ///Counts `beat` as one beat from `at` onwards, keeping the bpm in force there
+    pub fn set_beat(&mut self, at: Fraction, beat: Fraction) {
+        match self.find(at) {
+            Ok(i) => self.changes[i].tempo.beat = beat,
+            Err(i) => {
+                let tempo = Tempo::new(self.changes[i - 1].tempo.bpm, beat);
+                self.changes.insert(
+                    i,
+                    Change {
+                        at,
+                        tempo,
+                        gradual: false,
+                    },
+                );
+            }
+        }
+    }
+
+    ///Moves the tempo from `at` steadily towards the next change
+    pub fn ramp(&mut self, at: Fraction) {
+        match self.find(at) {
+            Ok(i) => self.changes[i].gradual = true,
+            Err(i) => {
+                let tempo = self.changes[i - 1].tempo;
+                self.changes.insert(
+                    i,
+                    Change {
+                        at,
+                        tempo,
+                        gradual: true,
+                    },
+                );
+            }
+        }
+    }
+
+    ///Holds the `length` starting at `at` for `factor` times as long
+    pub fn hold(&mut self, at: Fraction, length: Fraction, factor: Fraction) {
+        self.holds.push((at, length, factor));
+    }
+
+    ///the seconds from the start of the piece to `at`, ignoring holds
+    fn seconds(&self, at: Fraction) -> f64 {
+        self.changes
+            .iter()
+            .enumerate()
+            .take_while(|(_, c)| c.at < at)
+            .map(|(i, c)| {
+                let next = self.changes.get(i + 1);
+                let end = next.map(|n| n.at).filter(|end| *end < at).unwrap_or(at);
+                let x = (end - c.at).to_f64().unwrap();
+                match next {
+                    //the bpm changes linearly over the span
+                    Some(n) if c.gradual && n.tempo.bpm != c.tempo.bpm => {
+                        let (b0, b1) = (c.tempo.bpm as f64, n.tempo.bpm as f64);
+                        let slope = (b1 - b0) / (n.at - c.at).to_f64().unwrap();
+                        let beat = c.tempo.beat.to_f64().unwrap();
+                        60. / beat * ((b0 + slope * x) / b0).ln() / slope
+                    }
+                    _ => x * c.tempo.whole_note(),
+                }
+            })
+            .sum()
+    }
+
+    ///the time from the start of the piece to `at`
+    pub fn time(&self, at: Fraction) -> time::Duration {
+        let held = self
+            .holds
+            .iter()
+            .filter(|(start, ..)| *start < at)
+            .map(|&(start, length, factor)| {
+                let end = if start + length < at {
+                    start + length
+                } else {
+                    at
+                };
+                (factor.to_f64().unwrap() - 1.) * (self.seconds(end) - self.seconds(start))
+            })
+            .sum::<f64>();
+        time::Duration::from_secs_f64(self.seconds(at) + held)
+    }
+
+    ///the time taken by `length` starting at `at`
+    pub fn duration(&self, at: Fraction, length: Fraction) -> time::Duration {
+        self.time(at + length) - self.time(at)
+    }
+}
+
+impl Default for TempoMap {
+    fn default() -> Self {
+        Self {
+            changes: vec![Change {
+                at: Fraction::new(0u32, 1u32),
+                tempo: Tempo::default(),
+                gradual: false,
+            }],
+            holds: Vec::new(),
+        }
+    }
+}
+
+#[test]
+fn test_tempo_map() {
+    let quarter = Fraction::new(1u32, 4u32);
+    let mut tempo = TempoMap::default();
+    assert_eq!(tempo.time(quarter), time::Duration::from_secs_f32(0.5));
+    assert_eq!(
+        tempo.duration(quarter, Fraction::new(3u32, 8u32)),
+        time::Duration::from_secs_f32(0.75)
+    );
+
+    tempo.insert(Fraction::new(1u32, 1u32), Tempo::new(80, quarter));
+    assert_eq!(
+        tempo.time(Fraction::new(2u32, 1u32)),
+        time::Duration::from_secs_f32(5.)
+    );
+    assert_eq!(
+        tempo.duration(Fraction::new(3u32, 4u32), Fraction::new(1u32, 2u32)),
+        time::Duration::from_secs_f32(1.25)
+    );
+
+    tempo.insert(
+        Fraction::new(2u32, 1u32),
+        Tempo::new(60, Fraction::new(3u32, 8u32)),
+    );
+    assert_eq!(
+        tempo.duration(Fraction::new(2u32, 1u32), Fraction::new(3u32, 8u32)),
+        time::Duration::from_secs_f32(1.)
+    );
+
+    tempo.insert(Fraction::new(0u32, 1u32), Tempo::new(60, quarter));
+    assert_eq!(tempo.time(quarter), time::Duration::from_secs_f32(1.));
+}
+
+#[test]
+fn test_gradual() {
+    let quarter = Fraction::new(1u32, 4u32);
+    let mut tempo = TempoMap::default();
+    tempo.ramp(Fraction::new(0u32, 1u32));
+    tempo.insert(Fraction::new(1u32, 1u32), Tempo::new(60, quarter));
+    //slowing down from 120 to 60 takes between 2 and 4 seconds
+    let rit = tempo.time(Fraction::new(1u32, 1u32)).as_secs_f64();
+    assert!((rit - 4. * 2f64.ln()).abs() < 1e-6);
+    assert!(tempo.duration(Fraction::new(0u32, 1u32), quarter) < tempo.duration(quarter, quarter));
+    assert_eq!(
+        tempo.duration(Fraction::new(1u32, 1u32), quarter),
+        time::Duration::from_secs(1)
+    );
+
+    tempo.hold(
+        Fraction::new(1u32, 1u32),
+        quarter,
+        Fraction::new(3u32, 1u32),
+    );
+    assert_eq!(
+        tempo.duration(Fraction::new(1u32, 1u32), quarter),
+        time::Duration::from_secs(3)
+    );
+    assert_eq!(
+        tempo.duration(Fraction::new(5u32, 4u32), quarter),
+        time::Duration::from_secs(1)
+    );
+}